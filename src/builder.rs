@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+
+use crate::database::appeals::DEFAULT_APPEAL_COOLDOWN_DAYS;
+use crate::database::rate_limit::{RateLimit, RateLimiter};
+use crate::database::restrictions::EscalationPolicy;
+use crate::database::storage::{CollectionNames, Storage};
+use crate::id_converter::{ConfusableTable, IDConverter};
+use crate::id_namespaces::IdNamespace;
+use crate::roblox::{HttpClientConfig, RobloxApi, RobloxUrls, RobloxWebClient};
+use crate::{Backend, BackendConfig, BackendError};
+
+/// Configures and connects a `Backend`. Everything is checked in `build`, which fails instead of panicking.
+pub struct BackendBuilder {
+    roblox_cookie: Option<String>,
+    roblox_urls: RobloxUrls,
+    roblox_api: Option<Arc<dyn RobloxApi>>,
+    validate_cookie: bool,
+    http_config: HttpClientConfig,
+    id_alphabets: Option<(String, String)>,
+    id_checksum: bool,
+    id_obfuscation_key: Option<String>,
+    id_confusables: ConfusableTable,
+    id_namespaces: Vec<IdNamespace>,
+    mongodb: Option<(String, Option<String>)>,
+    mongodb_collections: CollectionNames,
+    storage: Option<Arc<dyn Storage>>,
+    appeal_cooldown: Duration,
+    escalation_policy: EscalationPolicy,
+    default_rate_limit: RateLimit
+}
+
+impl Default for BackendBuilder {
+    fn default() -> Self {
+        Self {
+            roblox_cookie: None,
+            roblox_urls: RobloxUrls::default(),
+            roblox_api: None,
+            validate_cookie: true,
+            http_config: HttpClientConfig::default(),
+            id_alphabets: None,
+            id_checksum: false,
+            id_obfuscation_key: None,
+            id_confusables: ConfusableTable::default(),
+            id_namespaces: Vec::new(),
+            mongodb: None,
+            mongodb_collections: CollectionNames::default(),
+            storage: None,
+            appeal_cooldown: Duration::days(DEFAULT_APPEAL_COOLDOWN_DAYS),
+            escalation_policy: EscalationPolicy::default(),
+            default_rate_limit: RateLimit::default()
+        }
+    }
+}
+
+impl BackendBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: BackendConfig) -> Self {
+        let mut builder = Self::new()
+            .roblox_cookie(config.roblox_cookie)
+            .roblox_urls(config.roblox_urls)
+            .id_alphabets(config.id_alphabet, config.id_numbers)
+            .id_checksum(config.id_checksum)
+            .id_confusables(config.id_confusables)
+            .mongodb_collections(config.collections);
+        for namespace in config.id_namespaces {
+            builder = builder.id_namespace(namespace);
+        }
+        if let Some(key) = config.id_obfuscation_key {
+            builder = builder.id_obfuscation_key(key);
+        }
+        if let Some(mongodb_url) = config.mongodb_url {
+            builder = builder.mongodb(mongodb_url, config.mongodb_database);
+        }
+        builder
+    }
+
+    pub fn roblox_cookie(mut self, cookie: impl Into<String>) -> Self {
+        self.roblox_cookie = Some(cookie.into());
+        self
+    }
+
+    pub fn roblox_urls(mut self, urls: RobloxUrls) -> Self {
+        self.roblox_urls = urls;
+        self
+    }
+
+    /// Uses the given implementation instead of the Roblox web APIs. The cookie and URLs are then ignored.
+    pub fn roblox_api<R: RobloxApi + 'static>(mut self, roblox_api: R) -> Self {
+        self.roblox_api = Some(Arc::new(roblox_api));
+        self
+    }
+
+    /// Whether `build` fetches the authenticated user to make sure the cookie works. On by default.
+    pub fn validate_cookie(mut self, validate: bool) -> Self {
+        self.validate_cookie = validate;
+        self
+    }
+
+    pub fn http_config(mut self, config: HttpClientConfig) -> Self {
+        self.http_config = config;
+        self
+    }
+
+    pub fn id_alphabets(mut self, alphabets: impl Into<String>, numbers: impl Into<String>) -> Self {
+        self.id_alphabets = Some((alphabets.into(), numbers.into()));
+        self
+    }
+
+    /// Appends a check character to short IDs so typos are caught. Off by default, since codes issued
+    /// without one stop decoding once it is turned on. Every ID alphabet then needs an even number of
+    /// characters.
+    pub fn id_checksum(mut self, checksum: bool) -> Self {
+        self.id_checksum = checksum;
+        self
+    }
+
+    /// Shuffles IDs with a permutation keyed by `key` before encoding them, so consecutive IDs don't get
+    /// consecutive codes. Changing the key changes every code.
+    pub fn id_obfuscation_key(mut self, key: impl Into<String>) -> Self {
+        self.id_obfuscation_key = Some(key.into());
+        self
+    }
+
+    /// Groups of characters lenient decoding treats as the same. Defaults to `ConfusableTable::default()`.
+    pub fn id_confusables(mut self, confusables: ConfusableTable) -> Self {
+        self.id_confusables = confusables;
+        self
+    }
+
+    /// Adds a kind of ID with its own prefix and alphabet. Checksum, obfuscation and confusables are shared
+    /// with the default namespace, but each namespace gets its own permutation.
+    pub fn id_namespace(mut self, namespace: IdNamespace) -> Self {
+        self.id_namespaces.push(namespace);
+        self
+    }
+
+    pub fn mongodb(mut self, mongodb_url: impl Into<String>, default_database: Option<String>) -> Self {
+        self.mongodb = Some((mongodb_url.into(), default_database));
+        self
+    }
+
+    pub fn mongodb_collections(mut self, collections: CollectionNames) -> Self {
+        self.mongodb_collections = collections;
+        self
+    }
+
+    /// Uses the given storage instead of connecting to MongoDB.
+    pub fn storage<S: Storage + 'static>(mut self, storage: S) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// How long a player has to wait after a rejected appeal before appealing the same ban again.
+    pub fn appeal_cooldown(mut self, cooldown: Duration) -> Self {
+        self.appeal_cooldown = cooldown;
+        self
+    }
+
+    /// When warnings turn into bans. Defaults to a 1 day ban after 3 warnings within 30 days.
+    pub fn escalation_policy(mut self, policy: EscalationPolicy) -> Self {
+        self.escalation_policy = policy;
+        self
+    }
+
+    /// Rate limit of API keys without one of their own. Defaults to 60 requests per minute with bursts of 60.
+    pub fn default_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.default_rate_limit = limit;
+        self
+    }
+
+    pub async fn build(self) -> Result<Backend, crate::Error> {
+        let (alphabets, numbers) = self.id_alphabets
+            .ok_or_else(|| BackendError::InvalidConfig("ID alphabets were not provided.".to_string()))?;
+        IDConverter::validate_alphabets(&alphabets, &numbers)?;
+        IdNamespace::validate(&self.id_namespaces, &alphabets, &self.id_confusables)?;
+        if self.id_checksum {
+            IDConverter::validate_checksum_alphabet(&alphabets).map_err(BackendError::InvalidConfig)?;
+            for namespace in &self.id_namespaces {
+                IDConverter::validate_checksum_alphabet(&namespace.alphabet)
+                    .map_err(|message| BackendError::InvalidConfig(format!("ID namespace {}: {}", namespace.name, message)))?;
+            }
+        }
+        let id_namespaces = self.id_namespaces.into_iter()
+            .map(|namespace| {
+                let key = self.id_obfuscation_key.as_ref().map(|key| format!("{}:{}", key, namespace.name));
+                let converter = IDConverter::new(&namespace.alphabet, &numbers)
+                    .with_checksum(self.id_checksum)
+                    .with_obfuscation_key(key.as_deref())
+                    .with_confusables(self.id_confusables.clone());
+                (namespace, converter)
+            })
+            .collect();
+        let id_generator = IDConverter::new(&alphabets, &numbers)
+            .with_checksum(self.id_checksum)
+            .with_obfuscation_key(self.id_obfuscation_key.as_deref())
+            .with_confusables(self.id_confusables);
+
+        let http_client = self.http_config.build_client()?;
+        let roblox: Arc<dyn RobloxApi> = match self.roblox_api {
+            Some(roblox_api) => roblox_api,
+            None => {
+                let cookie = self.roblox_cookie
+                    .filter(|cookie| !cookie.is_empty())
+                    .ok_or_else(|| BackendError::InvalidConfig("Roblox cookie was not provided.".to_string()))?;
+                Arc::new(RobloxWebClient::new(http_client.clone(), cookie, self.roblox_urls))
+            }
+        };
+
+        roblox.refresh_xcsrf_token().await?;
+        let roblox_user = if self.validate_cookie {
+            Some(roblox.fetch_authenticated_user().await?)
+        } else {
+            None
+        };
+
+        let mut backend = Backend {
+            http_client,
+            roblox,
+            roblox_user,
+            id_generator,
+            id_namespaces,
+            mongo_client: None,
+            storage: self.storage,
+            appeal_cooldown: self.appeal_cooldown,
+            escalation_policy: self.escalation_policy,
+            rate_limiter: RateLimiter::default(),
+            default_rate_limit: self.default_rate_limit
+        };
+        if backend.storage.is_none() {
+            if let Some((mongodb_url, default_database)) = self.mongodb {
+                backend.connect_mongodb_with_collections(mongodb_url, default_database, self.mongodb_collections).await?;
+            }
+        }
+
+        Ok(backend)
+    }
+}
@@ -0,0 +1,311 @@
+use std::env;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::storage::CollectionNames;
+use crate::id_converter::{AlphabetWarning, ConfusableTable, IDConverter};
+use crate::id_namespaces::IdNamespace;
+use crate::roblox::RobloxUrls;
+use crate::BackendError;
+
+const ENV_PREFIX: &str = "LB_";
+
+/// Everything needed to build a `Backend`, loadable from the environment or a TOML/JSON file.
+///
+/// Environment variables (a `.env` file is read first if present):
+/// `LB_ROBLOX_COOKIE`, `LB_ID_ALPHABET`, `LB_ID_NUMBERS` (required), `LB_ID_CHECKSUM`, `LB_ID_OBFUSCATION_KEY`,
+/// `LB_ID_CONFUSABLES` (comma-separated groups),
+/// `LB_MONGODB_URL`, `LB_MONGODB_DATABASE`,
+/// `LB_ROBLOX_AUTH_URL`, `LB_ROBLOX_ASSET_DELIVERY_URL`, `LB_ROBLOX_ECONOMY_V1_URL`, `LB_ROBLOX_ECONOMY_V2_URL`,
+/// `LB_ROBLOX_INVENTORY_URL`, `LB_ROBLOX_USERS_URL`,
+/// `LB_COLLECTION_BANS`, `LB_COLLECTION_HISTORY`, `LB_COLLECTION_RESTRICTIONS`, `LB_COLLECTION_APPEALS`,
+/// `LB_COLLECTION_LINKS`, `LB_COLLECTION_API_KEYS`.
+/// ID namespaces can only be set in a file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BackendConfig {
+    pub roblox_cookie: String,
+    #[serde(default)]
+    pub roblox_urls: RobloxUrls,
+    pub mongodb_url: Option<String>,
+    pub mongodb_database: Option<String>,
+    #[serde(default)]
+    pub collections: CollectionNames,
+    pub id_alphabet: String,
+    pub id_numbers: String,
+    /// Append a check character to short IDs.
+    #[serde(default)]
+    pub id_checksum: bool,
+    /// Key of the permutation applied to IDs before encoding. Unset means IDs are encoded as they are.
+    pub id_obfuscation_key: Option<String>,
+    /// Groups of characters lenient decoding treats as the same.
+    #[serde(default)]
+    pub id_confusables: ConfusableTable,
+    /// Extra kinds of IDs, each with its own prefix and alphabet.
+    #[serde(default)]
+    pub id_namespaces: Vec<IdNamespace>
+}
+
+// Hand-written so the cookie and other secrets never end up in logs.
+impl fmt::Debug for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendConfig")
+            .field("roblox_cookie", &"<redacted>")
+            .field("roblox_urls", &self.roblox_urls)
+            .field("mongodb_url", &self.mongodb_url.as_ref().map(|_| "<redacted>"))
+            .field("mongodb_database", &self.mongodb_database)
+            .field("collections", &self.collections)
+            .field("id_alphabet", &self.id_alphabet)
+            .field("id_numbers", &self.id_numbers)
+            .field("id_checksum", &self.id_checksum)
+            .field("id_obfuscation_key", &self.id_obfuscation_key.as_ref().map(|_| "<redacted>"))
+            .field("id_confusables", &self.id_confusables)
+            .field("id_namespaces", &self.id_namespaces)
+            .finish()
+    }
+}
+
+fn invalid_config(message: String) -> BackendError {
+    BackendError::InvalidConfig(message)
+}
+
+fn optional_var(name: &str) -> Option<String> {
+    env::var(format!("{}{}", ENV_PREFIX, name)).ok().filter(|value| !value.is_empty())
+}
+
+fn required_var(name: &str) -> Result<String, crate::Error> {
+    optional_var(name).ok_or_else(|| invalid_config(format!("Environment variable {}{} is not set.", ENV_PREFIX, name)))
+}
+
+fn bool_var(name: &str) -> Result<bool, crate::Error> {
+    match optional_var(name).as_deref() {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(value) => Err(invalid_config(format!("Environment variable {}{} must be true or false, got {}.", ENV_PREFIX, name, value)))
+    }
+}
+
+fn override_var(target: &mut String, name: &str) {
+    if let Some(value) = optional_var(name) {
+        *target = value;
+    }
+}
+
+impl BackendConfig {
+    pub fn from_env() -> Result<Self, crate::Error> {
+        dotenv::dotenv().ok();
+
+        let mut roblox_urls = RobloxUrls::default();
+        override_var(&mut roblox_urls.auth, "ROBLOX_AUTH_URL");
+        override_var(&mut roblox_urls.asset_delivery, "ROBLOX_ASSET_DELIVERY_URL");
+        override_var(&mut roblox_urls.economy_v1, "ROBLOX_ECONOMY_V1_URL");
+        override_var(&mut roblox_urls.economy_v2, "ROBLOX_ECONOMY_V2_URL");
+        override_var(&mut roblox_urls.inventory, "ROBLOX_INVENTORY_URL");
+        override_var(&mut roblox_urls.users, "ROBLOX_USERS_URL");
+
+        let mut collections = CollectionNames::default();
+        override_var(&mut collections.bans, "COLLECTION_BANS");
+        override_var(&mut collections.history, "COLLECTION_HISTORY");
+        override_var(&mut collections.restrictions, "COLLECTION_RESTRICTIONS");
+        override_var(&mut collections.appeals, "COLLECTION_APPEALS");
+        override_var(&mut collections.links, "COLLECTION_LINKS");
+        override_var(&mut collections.api_keys, "COLLECTION_API_KEYS");
+
+        let id_confusables = match optional_var("ID_CONFUSABLES") {
+            Some(groups) => ConfusableTable { groups: groups.split(',').map(|group| group.trim().to_string()).collect() },
+            None => ConfusableTable::default()
+        };
+
+        Ok(Self {
+            roblox_cookie: required_var("ROBLOX_COOKIE")?,
+            roblox_urls,
+            mongodb_url: optional_var("MONGODB_URL"),
+            mongodb_database: optional_var("MONGODB_DATABASE"),
+            collections,
+            id_alphabet: required_var("ID_ALPHABET")?,
+            id_numbers: required_var("ID_NUMBERS")?,
+            id_checksum: bool_var("ID_CHECKSUM")?,
+            id_obfuscation_key: optional_var("ID_OBFUSCATION_KEY"),
+            id_confusables,
+            id_namespaces: Vec::new()
+        })
+    }
+
+    /// Characters of `id_alphabet` that lenient decoding can't tell apart.
+    pub fn id_alphabet_warnings(&self) -> Vec<AlphabetWarning> {
+        IDConverter::alphabet_warnings(&self.id_alphabet, &self.id_confusables)
+    }
+
+    /// Reads a `.toml` or `.json` file, picked by extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| invalid_config(format!("Could not read {}: {}", path.display(), err)))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml_str(&contents),
+            Some("json") => Self::from_json_str(&contents),
+            _ => Err(invalid_config(format!("Unsupported config file type: {}", path.display())))
+        }
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, crate::Error> {
+        toml::from_str(contents).map_err(|err| invalid_config(format!("Invalid TOML config: {}", err)))
+    }
+
+    pub fn from_json_str(contents: &str) -> Result<Self, crate::Error> {
+        serde_json::from_str(contents).map_err(|err| invalid_config(format!("Invalid JSON config: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// The environment is shared by every test thread, so tests that set variables take turns.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const REQUIRED: [(&str, &str); 3] = [("ROBLOX_COOKIE", "cookie"), ("ID_ALPHABET", "abcdefghij"), ("ID_NUMBERS", "0123456789")];
+
+    fn from_env_with(vars: &[(&str, &str)]) -> Result<BackendConfig, crate::Error> {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let clear = || {
+            for (name, _) in env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)) {
+                env::remove_var(name);
+            }
+        };
+        clear();
+        for (name, value) in vars {
+            env::set_var(format!("{}{}", ENV_PREFIX, name), value);
+        }
+        let config = BackendConfig::from_env();
+        clear();
+        config
+    }
+
+    fn with_required(extra: &[(&'static str, &'static str)]) -> Vec<(&'static str, &'static str)> {
+        REQUIRED.iter().chain(extra).copied().collect()
+    }
+
+    fn assert_invalid(result: Result<BackendConfig, crate::Error>) -> String {
+        match result {
+            Err(BackendError::InvalidConfig(message)) => message,
+            result => panic!("{:?}", result)
+        }
+    }
+
+    #[test]
+    fn requires_the_cookie_and_id_alphabets() {
+        let config = from_env_with(&REQUIRED).unwrap();
+        assert_eq!(config.roblox_cookie, "cookie");
+        assert_eq!((config.id_alphabet.as_str(), config.id_numbers.as_str()), ("abcdefghij", "0123456789"));
+        assert_eq!((config.mongodb_url, config.mongodb_database), (None, None));
+        assert!(!config.id_checksum);
+        assert_eq!(config.id_confusables, ConfusableTable::default());
+
+        for missing in REQUIRED.map(|(name, _)| name) {
+            let vars: Vec<_> = REQUIRED.into_iter().filter(|(name, _)| *name != missing).collect();
+            let message = assert_invalid(from_env_with(&vars));
+            assert_eq!(message, format!("Environment variable LB_{} is not set.", missing));
+        }
+
+        // Empty variables count as unset.
+        let vars = [("ROBLOX_COOKIE", ""), ("ID_ALPHABET", "abcdefghij"), ("ID_NUMBERS", "0123456789")];
+        assert_invalid(from_env_with(&vars));
+    }
+
+    #[test]
+    fn parses_checksum_flags() {
+        for (value, expected) in [("true", true), ("1", true), ("false", false), ("0", false), ("", false)] {
+            let config = from_env_with(&with_required(&[("ID_CHECKSUM", value)])).unwrap();
+            assert_eq!(config.id_checksum, expected, "{:?}", value);
+        }
+        for value in ["yes", "TRUE", "2"] {
+            let message = assert_invalid(from_env_with(&with_required(&[("ID_CHECKSUM", value)])));
+            assert_eq!(message, format!("Environment variable LB_ID_CHECKSUM must be true or false, got {}.", value));
+        }
+    }
+
+    #[test]
+    fn reads_overrides_from_the_environment() {
+        let config = from_env_with(&with_required(&[
+            ("ID_CONFUSABLES", "0Oo, 1Il ,uv"),
+            ("MONGODB_URL", "mongodb://localhost"),
+            ("MONGODB_DATABASE", "game"),
+            ("ID_OBFUSCATION_KEY", "key"),
+            ("ROBLOX_AUTH_URL", "http://localhost/auth"),
+            ("ROBLOX_USERS_URL", "http://localhost/users"),
+            ("COLLECTION_BANS", "test_bans"),
+            ("COLLECTION_API_KEYS", "test_keys")
+        ])).unwrap();
+
+        assert_eq!(config.id_confusables.groups, vec!["0Oo", "1Il", "uv"]);
+        assert_eq!(config.mongodb_url.as_deref(), Some("mongodb://localhost"));
+        assert_eq!(config.mongodb_database.as_deref(), Some("game"));
+        assert_eq!(config.id_obfuscation_key.as_deref(), Some("key"));
+        assert_eq!(config.roblox_urls.auth, "http://localhost/auth");
+        assert_eq!(config.roblox_urls.users, "http://localhost/users");
+        assert_eq!(config.roblox_urls.inventory, RobloxUrls::default().inventory);
+        assert_eq!(config.collections.bans, "test_bans");
+        assert_eq!(config.collections.api_keys, "test_keys");
+        assert_eq!(config.collections.history, CollectionNames::default().history);
+        assert!(config.id_namespaces.is_empty());
+    }
+
+    #[test]
+    fn reads_toml_and_json() {
+        let toml = r#"
+            roblox_cookie = "cookie"
+            id_alphabet = "abcdefghij"
+            id_numbers = "0123456789"
+            id_checksum = true
+            id_confusables = ["0Oo", "uv"]
+
+            [roblox_urls]
+            auth = "http://localhost/auth"
+
+            [collections]
+            bans = "test_bans"
+
+            [[id_namespaces]]
+            name = "maps"
+            prefix = "M-"
+            alphabet = "ABCDEFGHJK"
+        "#;
+        let json = r#"{
+            "roblox_cookie": "cookie",
+            "id_alphabet": "abcdefghij",
+            "id_numbers": "0123456789",
+            "id_checksum": true,
+            "id_confusables": ["0Oo", "uv"],
+            "roblox_urls": { "auth": "http://localhost/auth" },
+            "collections": { "bans": "test_bans" },
+            "id_namespaces": [{ "name": "maps", "prefix": "M-", "alphabet": "ABCDEFGHJK" }]
+        }"#;
+
+        for config in [BackendConfig::from_toml_str(toml).unwrap(), BackendConfig::from_json_str(json).unwrap()] {
+            assert!(config.id_checksum);
+            assert_eq!(config.id_confusables.groups, vec!["0Oo", "uv"]);
+            assert_eq!(config.roblox_urls.auth, "http://localhost/auth");
+            assert_eq!(config.roblox_urls.users, RobloxUrls::default().users);
+            assert_eq!(config.collections.bans, "test_bans");
+            assert_eq!(config.collections.links, CollectionNames::default().links);
+            assert_eq!(config.id_namespaces, vec![IdNamespace::new("maps", "M-", "ABCDEFGHJK")]);
+        }
+    }
+
+    #[test]
+    fn file_configs_fall_back_to_defaults() {
+        let config = BackendConfig::from_json_str(r#"{ "roblox_cookie": "c", "id_alphabet": "ab", "id_numbers": "01" }"#).unwrap();
+        assert!(!config.id_checksum);
+        assert_eq!(config.id_confusables, ConfusableTable::default());
+        assert_eq!(config.roblox_urls.auth, RobloxUrls::default().auth);
+        assert_eq!(config.collections.bans, CollectionNames::default().bans);
+
+        assert_invalid(BackendConfig::from_json_str(r#"{ "roblox_cookie": "c", "id_alphabet": "ab" }"#));
+        assert_invalid(BackendConfig::from_toml_str("roblox_cookie = 1"));
+    }
+}
@@ -1,12 +1,55 @@
-use mongodb::{bson::doc, Collection};
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
 use serde::{ Deserialize, Serialize };
+use sha2::{Digest, Sha256};
 
-use crate::Backend;
-use crate::IDConverter;
+use crate::{Backend, BackendError};
 use crate::utils::datetime_now;
+use super::rate_limit::RateLimit;
+use super::storage::{ApiKeyChanges, ApiKeyUpdate};
+
+/// Prefix of every generated key, so leaked keys are easy to recognize.
+pub const API_KEY_PREFIX: &str = "lb_";
+/// Random characters after the prefix. 40 alphanumeric characters hold about 238 bits.
+const API_KEY_RANDOM_LENGTH: usize = 40;
+const MAX_GENERATION_ATTEMPTS: u32 = 3;
+/// Attempts at updating a key that keeps changing between being read and written.
+const MAX_UPDATE_ATTEMPTS: u32 = 3;
+/// `assign_owner` of keys without an owner.
+const NO_OWNER: &str = "None";
+
+/// What a key is allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiScope {
+    #[serde(rename = "bans:read")]
+    BansRead,
+    #[serde(rename = "bans:write")]
+    BansWrite,
+    #[serde(rename = "maps:whitelist")]
+    MapsWhitelist,
+    #[serde(rename = "assets:download")]
+    AssetsDownload
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 4] = [Self::BansRead, Self::BansWrite, Self::MapsWhitelist, Self::AssetsDownload];
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+// Keys created before scopes existed could do anything, and keep doing so.
+fn legacy_scopes() -> Vec<ApiScope> {
+    ApiScope::ALL.to_vec()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiKey {
+    /// Identifies the key for management without knowing it. Empty for keys created before IDs existed,
+    /// until `migrate_api_keys` runs.
+    #[serde(rename = "keyId", default)]
+    pub key_id: String,
+    /// SHA-256 of the key, hex encoded. Holds the key itself for keys created before hashing, see `hashed`.
     pub value: String,
     #[serde(rename = "assignOwner")]
     pub assign_owner: String,
@@ -15,99 +58,325 @@ pub struct ApiKey {
     pub enabled: bool,
     #[serde(rename = "timeCreated")]
     pub time_created: f64,
+    #[serde(default)]
+    pub hashed: bool,
+    #[serde(default = "legacy_scopes")]
+    pub scopes: Vec<ApiScope>,
+    /// `None` for keys that never expire.
+    #[serde(rename = "expiresAt", with = "chrono::serde::ts_milliseconds_option", default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Overrides the backend's default rate limit for this key.
+    #[serde(rename = "rateLimit", default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(rename = "lastUsed", with = "chrono::serde::ts_milliseconds_option", default)]
+    pub last_used: Option<DateTime<Utc>>,
+    #[serde(rename = "totalRequests", default)]
+    pub total_requests: u64,
+    /// Requests per UTC day, keyed by `YYYY-MM-DD`.
+    #[serde(rename = "dailyRequests", default)]
+    pub daily_requests: BTreeMap<String, u64>,
+    /// Incremented by every change to the key, so changes based on an outdated read are refused. Counting
+    /// usage leaves it alone, since usage counters are never part of a change.
+    #[serde(default)]
+    pub version: u64
 }
 
-impl Backend {
-    pub(super) async fn generate_api_key(&self) -> Result<String, crate::Error> {
-        let database = self.get_database();
-        let api_keys_collection: Collection<ApiKey> = database.collection("apikeys");
-        
-        // my reaction when rust
-        let api_key_generator: IDConverter = IDConverter::new(
-            &"qwertyuiopasdfghjklzxcvbnm0192837465".to_string(),
-            &"5432189076".to_string()
-        );
+/// Key of `ApiKey::daily_requests` for the day.
+pub fn usage_day(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
 
-        let doc_count: u64 = api_keys_collection.count_documents(None, None).await?;
-        api_key_generator.to_short(doc_count * 8 + datetime_now() * 2)
+impl ApiKey {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
     }
 
-    pub async fn find_api_key_entry(&self, api_key: &str) -> Result<Option<ApiKey>, crate::Error> {
-        let database = self.get_database();
+    /// Enabled and not expired.
+    pub fn is_usable_at(&self, now: DateTime<Utc>) -> bool {
+        self.enabled && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    pub fn requests_on(&self, date: NaiveDate) -> u64 {
+        self.daily_requests.get(&usage_day(date)).copied().unwrap_or(0)
+    }
+
+    /// The Roblox user the key is assigned to.
+    pub fn roblox_owner(&self) -> Option<u64> {
+        self.assign_owner.parse().ok()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApiKeyOwner {
+    pub roblox_id: Option<u64>,
+    pub discord_id: Option<u64>
+}
+
+/// A freshly created or rotated key. `secret` is not stored anywhere and cannot be recovered later.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssuedApiKey {
+    pub key_id: String,
+    pub secret: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Authorization {
+    /// `assign_owner` of the key.
+    pub owner: String,
+    pub granted: bool
+}
 
-        let api_keys_collection: Collection<ApiKey> = database.collection("apikeys");
+/// The value stored for a key.
+pub fn hash_api_key(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
 
-        let result = api_keys_collection.find_one(
-            doc! { 
-                "value": api_key.to_string()
-            },
-            None
-        ).await?;
+fn generate_api_key() -> String {
+    let random: String = OsRng
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_RANDOM_LENGTH)
+        .map(char::from)
+        .collect();
+    format!("{}{}", API_KEY_PREFIX, random)
+}
 
-        Ok(result)
+fn owner_changes(owner: &ApiKeyOwner) -> ApiKeyChanges {
+    ApiKeyChanges {
+        assign_owner: Some(owner.roblox_id.map_or(NO_OWNER.to_string(), |roblox_id| roblox_id.to_string())),
+        associated_discord_user: Some(owner.discord_id.map(|discord_id| discord_id.to_string())),
+        ..ApiKeyChanges::default()
     }
+}
 
-    pub async fn api_key_entry_exist(&self, api_key: &str) -> Result<bool, crate::Error> {
-        let result = self.find_api_key_entry(api_key).await?;
+impl Backend {
+    pub async fn find_api_key_entry(&self, api_key: &str) -> Result<Option<ApiKey>, crate::Error> {
+        let storage = self.storage()?;
+        if let Some(entry) = storage.find_api_key(&hash_api_key(api_key)).await?.filter(|entry| entry.hashed) {
+            return Ok(Some(entry))
+        }
 
-        match result {
-            Some(_) => Ok(true),
-            None => Ok(false)
+        // Keys created before hashing are stored as-is until `migrate_api_keys` runs.
+        Ok(storage.find_api_key(api_key).await?.filter(|entry| !entry.hashed))
+    }
+
+    pub async fn find_api_key_by_id(&self, key_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        if key_id.is_empty() {
+            return Ok(None)
         }
+        self.storage()?.find_api_key_by_id(key_id).await
     }
 
-    pub async fn create_api_key_entry(&self) -> Result<(), crate::Error> {
-        let database = self.get_database();
-        
-        let api_keys_collection: Collection<ApiKey> = database.collection("apikeys");
-        let new_api_key = self.generate_api_key().await?;
+    pub async fn api_key_entry_exist(&self, api_key: &str) -> Result<bool, crate::Error> {
+        let result = self.find_api_key_entry(api_key).await?;
+
+        Ok(result.is_some())
+    }
 
-        let doc = ApiKey {
-            value: new_api_key,
-            assign_owner: "None".to_string(),
+    /// Creates a key and returns it with its ID. Only its hash is stored, so this is the only time the key
+    /// can be read.
+    pub async fn create_api_key(&self, owner: ApiKeyOwner, scopes: Vec<ApiScope>, expires_at: Option<DateTime<Utc>>) -> Result<IssuedApiKey, crate::Error> {
+        let mut entry = ApiKey {
+            key_id: ObjectId::new().to_hex(),
+            value: String::new(),
+            assign_owner: NO_OWNER.to_string(),
             associated_discord_user: None,
             enabled: true,
-            time_created: datetime_now() as f64
+            time_created: datetime_now() as f64,
+            hashed: true,
+            scopes,
+            expires_at,
+            rate_limit: None,
+            last_used: None,
+            total_requests: 0,
+            daily_requests: BTreeMap::new(),
+            version: 0
         };
-        api_keys_collection.insert_one(doc, None).await?;
-        Ok(())
+        owner_changes(&owner).apply(&mut entry);
+
+        for _ in 0..MAX_GENERATION_ATTEMPTS {
+            let secret = generate_api_key();
+            entry.value = hash_api_key(&secret);
+            if self.storage()?.insert_api_key(entry.clone()).await? {
+                return Ok(IssuedApiKey { key_id: entry.key_id, secret })
+            }
+        }
+
+        Err(BackendError::DuplicateApiKey)
     }
 
-    pub async fn search_api_key_entries_with_roblox_id(&self, roblox_id: u64) -> Result<Option<ApiKey>, crate::Error> {
-        let database = self.get_database();
-        
-        let api_keys_collection: Collection<ApiKey> = database.collection("apikeys");
+    /// Creates an unassigned key that never expires and returns it.
+    pub async fn create_api_key_entry(&self, scopes: Vec<ApiScope>) -> Result<String, crate::Error> {
+        let issued = self.create_api_key(ApiKeyOwner::default(), scopes, None).await?;
+        Ok(issued.secret)
+    }
+
+    /// Gives the key a new secret, keeping its ID, owner, scopes and expiry. The old secret stops working.
+    pub async fn rotate_api_key(&self, key_id: &str) -> Result<IssuedApiKey, crate::Error> {
+        let mut error = BackendError::DuplicateApiKey;
 
-        let result = api_keys_collection.find_one(
-            doc! { 
-                "assignOwner": roblox_id.to_string()
-            },
-            None
-        ).await?;
+        for _ in 0..MAX_GENERATION_ATTEMPTS {
+            let current = self.api_key_by_id(key_id).await?;
+            let secret = generate_api_key();
+            let changes = ApiKeyChanges { value: Some(hash_api_key(&secret)), hashed: Some(true), ..ApiKeyChanges::default() };
+            match self.storage()?.update_api_key(&current, changes).await? {
+                ApiKeyUpdate::Updated => return Ok(IssuedApiKey { key_id: current.key_id, secret }),
+                ApiKeyUpdate::Stale => error = BackendError::ApiKeyConflict(key_id.to_string()),
+                ApiKeyUpdate::DuplicateValue => error = BackendError::DuplicateApiKey
+            }
+        }
 
-        Ok(result)
+        Err(error)
     }
 
-    pub async fn search_api_key_entries_with_discord_id(&self, discord_id: u64) -> Result<Option<ApiKey>, crate::Error> {
-        let database = self.get_database();
-        
-        let api_keys_collection: Collection<ApiKey> = database.collection("apikeys");
+    /// Disables the key. It stays stored, so its owner and history can still be looked up.
+    pub async fn revoke_api_key(&self, key_id: &str) -> Result<(), crate::Error> {
+        self.update_api_key(key_id, ApiKeyChanges { enabled: Some(false), ..ApiKeyChanges::default() }).await
+    }
+
+    /// Removes the key entirely. Returns whether it existed.
+    pub async fn delete_api_key(&self, key_id: &str) -> Result<bool, crate::Error> {
+        if key_id.is_empty() {
+            return Ok(false)
+        }
+        self.storage()?.delete_api_key(key_id).await
+    }
+
+    /// `None` makes the key never expire.
+    pub async fn set_api_key_expiry(&self, key_id: &str, expires_at: Option<DateTime<Utc>>) -> Result<(), crate::Error> {
+        self.update_api_key(key_id, ApiKeyChanges { expires_at: Some(expires_at), ..ApiKeyChanges::default() }).await
+    }
+
+    pub async fn reassign_api_key(&self, key_id: &str, owner: ApiKeyOwner) -> Result<(), crate::Error> {
+        self.update_api_key(key_id, owner_changes(&owner)).await
+    }
+
+    pub async fn set_api_key_scopes(&self, key_id: &str, scopes: Vec<ApiScope>) -> Result<(), crate::Error> {
+        self.update_api_key(key_id, ApiKeyChanges { scopes: Some(scopes), ..ApiKeyChanges::default() }).await
+    }
+
+    /// `None` makes the key use the backend's default rate limit.
+    pub async fn set_api_key_rate_limit(&self, key_id: &str, rate_limit: Option<RateLimit>) -> Result<(), crate::Error> {
+        self.update_api_key(key_id, ApiKeyChanges { rate_limit: Some(rate_limit), ..ApiKeyChanges::default() }).await
+    }
+
+    /// Checks whether the key may use `scope`. Fails with `InvalidApiKey` for unknown, disabled or expired keys.
+    pub async fn authorize(&self, api_key: &str, scope: ApiScope) -> Result<Authorization, crate::Error> {
+        let now = Utc::now();
+        let entry = self.find_api_key_entry(api_key).await?
+            .filter(|entry| entry.is_usable_at(now))
+            .ok_or(BackendError::InvalidApiKey)?;
+
+        Ok(Authorization { granted: entry.has_scope(scope), owner: entry.assign_owner })
+    }
+
+    /// Replaces every key stored in plain text with its hash and gives keys without an ID one. Returns how
+    /// many keys were migrated.
+    pub async fn migrate_api_keys(&self) -> Result<u64, crate::Error> {
+        let storage = self.storage()?;
+        let mut migrated: u64 = 0;
+
+        for current in storage.api_keys_needing_migration().await? {
+            let mut changes = ApiKeyChanges::default();
+            if !current.hashed {
+                changes.value = Some(hash_api_key(&current.value));
+                changes.hashed = Some(true);
+            }
+            if current.key_id.is_empty() {
+                changes.key_id = Some(ObjectId::new().to_hex());
+            }
+            // Stale keys were changed meanwhile, most likely migrated by someone else.
+            if storage.update_api_key(&current, changes).await? == ApiKeyUpdate::Updated {
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
 
-        let result = api_keys_collection.find_one(
-            doc! { 
-                "associatedDiscordUser": Some(discord_id.to_string())
-            },
-            None
-        ).await?;
+    pub async fn search_api_key_entries_with_roblox_id(&self, roblox_id: u64) -> Result<Option<ApiKey>, crate::Error> {
+        self.storage()?.find_api_key_by_owner(&roblox_id.to_string()).await
+    }
 
-        Ok(result)
+    pub async fn search_api_key_entries_with_discord_id(&self, discord_id: u64) -> Result<Option<ApiKey>, crate::Error> {
+        self.storage()?.find_api_key_by_discord_user(&discord_id.to_string()).await
     }
 
+    /// Whether the key exists, is enabled and has not expired.
     pub async fn is_valid_api_key(&self, api_key: &str) -> Result<bool, crate::Error> {
         let api_key_entry = self.find_api_key_entry(api_key).await?;
         match api_key_entry {
-            Some(entry) => Ok(entry.enabled == true),
+            Some(entry) => Ok(entry.is_usable_at(Utc::now())),
             None => Ok(false)
         }
     }
-}
\ No newline at end of file
+
+    async fn api_key_by_id(&self, key_id: &str) -> Result<ApiKey, crate::Error> {
+        self.find_api_key_by_id(key_id).await?
+            .ok_or_else(|| BackendError::ApiKeyNotFound(key_id.to_string()))
+    }
+
+    /// Applies `changes` to the stored key, starting over if the key changes in between, like when it is
+    /// rotated at the same time.
+    async fn update_api_key(&self, key_id: &str, changes: ApiKeyChanges) -> Result<(), crate::Error> {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let current = self.api_key_by_id(key_id).await?;
+            match self.storage()?.update_api_key(&current, changes.clone()).await? {
+                ApiKeyUpdate::Updated => return Ok(()),
+                ApiKeyUpdate::Stale => continue,
+                ApiKeyUpdate::DuplicateValue => return Err(BackendError::DuplicateApiKey)
+            }
+        }
+
+        Err(BackendError::ApiKeyConflict(key_id.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::test_support::backend;
+    use super::*;
+
+    #[test]
+    fn rotation_keeps_a_revocation_made_after_its_read() {
+        let backend = backend();
+        let issued = block_on(backend.create_api_key(ApiKeyOwner::default(), vec![ApiScope::BansRead], None)).unwrap();
+
+        // Rotation reads the key, then the revocation lands before rotation writes.
+        let read_by_rotation = block_on(backend.api_key_by_id(&issued.key_id)).unwrap();
+        block_on(backend.revoke_api_key(&issued.key_id)).unwrap();
+        let changes = ApiKeyChanges { value: Some(hash_api_key("lb_other")), hashed: Some(true), ..ApiKeyChanges::default() };
+        let result = block_on(backend.storage().unwrap().update_api_key(&read_by_rotation, changes)).unwrap();
+        assert_eq!(result, ApiKeyUpdate::Stale);
+
+        // Rotating again starts from the revoked key and keeps it revoked.
+        let rotated = block_on(backend.rotate_api_key(&issued.key_id)).unwrap();
+        assert!(!block_on(backend.is_valid_api_key(&rotated.secret)).unwrap());
+        assert!(!block_on(backend.api_key_by_id(&issued.key_id)).unwrap().enabled);
+    }
+
+    #[test]
+    fn created_api_keys_validate() {
+        let backend = backend();
+        let owner = ApiKeyOwner { roblox_id: Some(42), discord_id: None };
+        let issued = block_on(backend.create_api_key(owner, vec![ApiScope::BansRead], None)).unwrap();
+
+        assert!(block_on(backend.is_valid_api_key(&issued.secret)).unwrap());
+        assert!(!block_on(backend.is_valid_api_key("lb_unknown")).unwrap());
+        let authorization = block_on(backend.authorize(&issued.secret, ApiScope::BansRead)).unwrap();
+        assert_eq!(authorization.owner, "42");
+        assert!(authorization.granted);
+        assert!(!block_on(backend.authorize(&issued.secret, ApiScope::BansWrite)).unwrap().granted);
+
+        let rotated = block_on(backend.rotate_api_key(&issued.key_id)).unwrap();
+        assert!(!block_on(backend.is_valid_api_key(&issued.secret)).unwrap());
+        assert!(block_on(backend.is_valid_api_key(&rotated.secret)).unwrap());
+
+        block_on(backend.revoke_api_key(&issued.key_id)).unwrap();
+        assert!(!block_on(backend.is_valid_api_key(&rotated.secret)).unwrap());
+    }
+}
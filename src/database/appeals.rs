@@ -0,0 +1,236 @@
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{ Deserialize, Serialize };
+
+use crate::{Backend, BackendError};
+
+pub const DEFAULT_APPEAL_COOLDOWN_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppealStatus {
+    Pending,
+    Accepted,
+    Rejected
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BanAppeal {
+    #[serde(rename = "appealId")]
+    pub id: String,
+    #[serde(rename = "userId")]
+    pub user_id: i64,
+    /// `banned_time` of the appealed ban, which tells it apart from earlier or later bans of the same player.
+    #[serde(rename = "banTime", with = "chrono::serde::ts_milliseconds")]
+    pub ban_time: DateTime<Utc>,
+    pub message: String,
+    pub status: AppealStatus,
+    #[serde(rename = "submittedAt", with = "chrono::serde::ts_milliseconds")]
+    pub submitted_at: DateTime<Utc>,
+    #[serde(rename = "reviewedBy")]
+    pub reviewed_by: Option<String>,
+    #[serde(rename = "reviewedAt", with = "chrono::serde::ts_milliseconds_option", default)]
+    pub reviewed_at: Option<DateTime<Utc>>,
+    #[serde(rename = "moderatorNote")]
+    pub moderator_note: Option<String>
+}
+
+impl Backend {
+    pub fn set_appeal_cooldown(&mut self, cooldown: Duration) {
+        self.appeal_cooldown = cooldown;
+    }
+
+    /// Appeals the player's active ban. Only one appeal per ban can be pending, and after a rejection the
+    /// player has to wait out the appeal cooldown.
+    pub async fn submit_appeal(&self, user_id: u64, message: &str) -> Result<BanAppeal, crate::Error> {
+        let ban = self.get_active_ban(user_id).await?.ok_or(BackendError::BanNotFound(user_id))?;
+        let now = Utc::now();
+
+        let previous_appeals: Vec<BanAppeal> = self.storage()?.appeals_for_user(user_id).await?
+            .into_iter()
+            .filter(|appeal| appeal.ban_time == ban.banned_time)
+            .collect();
+        if previous_appeals.iter().any(|appeal| appeal.status == AppealStatus::Pending) {
+            return Err(BackendError::InvalidAppeal("An appeal for this ban is already pending.".to_string()))
+        }
+        let last_rejection = previous_appeals.iter()
+            .filter(|appeal| appeal.status == AppealStatus::Rejected)
+            .filter_map(|appeal| appeal.reviewed_at)
+            .max();
+        if let Some(retry_after) = last_rejection.map(|rejected_at| rejected_at + self.appeal_cooldown) {
+            if retry_after > now {
+                return Err(BackendError::AppealCooldown { retry_after })
+            }
+        }
+
+        let appeal = BanAppeal {
+            id: ObjectId::new().to_hex(),
+            user_id: user_id as i64,
+            ban_time: ban.banned_time,
+            message: message.to_string(),
+            status: AppealStatus::Pending,
+            submitted_at: now,
+            reviewed_by: None,
+            reviewed_at: None,
+            moderator_note: None
+        };
+        // Checked again by the store, in case another appeal was submitted since the check above.
+        if !self.storage()?.insert_appeal(appeal.clone()).await? {
+            return Err(BackendError::InvalidAppeal("An appeal for this ban is already pending.".to_string()))
+        }
+        Ok(appeal)
+    }
+
+    pub async fn get_appeal(&self, appeal_id: &str) -> Result<Option<BanAppeal>, crate::Error> {
+        self.storage()?.find_appeal(appeal_id).await
+    }
+
+    /// Every appeal the player has submitted, oldest first.
+    pub async fn get_player_appeals(&self, user_id: u64) -> Result<Vec<BanAppeal>, crate::Error> {
+        self.storage()?.appeals_for_user(user_id).await
+    }
+
+    /// Appeals waiting for review, oldest first.
+    pub async fn list_pending_appeals(&self, limit: u32) -> Result<Vec<BanAppeal>, crate::Error> {
+        self.storage()?.pending_appeals(limit).await
+    }
+
+    /// Accepts the appeal and lifts the ban it refers to, recording the unban in the moderation history.
+    pub async fn accept_appeal(&self, appeal_id: &str, moderator: &str, note: &str) -> Result<BanAppeal, crate::Error> {
+        let appeal = self.find_pending_appeal(appeal_id).await?;
+        // Closed first, so a moderator reviewing the same appeal at the same time can't have it both ways.
+        let appeal = self.close_appeal(appeal, AppealStatus::Accepted, moderator, note).await?;
+
+        let current_ban = self.find_ban_entry(appeal.user_id as u64).await?;
+        if current_ban.is_some_and(|ban| ban.banned_time == appeal.ban_time) {
+            self.unban_player(appeal.user_id as u64, moderator, &format!("Appeal accepted: {}", note)).await?;
+        }
+
+        Ok(appeal)
+    }
+
+    pub async fn reject_appeal(&self, appeal_id: &str, moderator: &str, note: &str) -> Result<BanAppeal, crate::Error> {
+        let appeal = self.find_pending_appeal(appeal_id).await?;
+        self.close_appeal(appeal, AppealStatus::Rejected, moderator, note).await
+    }
+
+    async fn find_pending_appeal(&self, appeal_id: &str) -> Result<BanAppeal, crate::Error> {
+        let appeal = self.get_appeal(appeal_id).await?
+            .ok_or_else(|| BackendError::InvalidAppeal(format!("Appeal {} does not exist.", appeal_id)))?;
+        if appeal.status != AppealStatus::Pending {
+            return Err(already_reviewed(appeal_id))
+        }
+        Ok(appeal)
+    }
+
+    /// Fails if the appeal was reviewed by someone else since it was read.
+    async fn close_appeal(&self, mut appeal: BanAppeal, status: AppealStatus, moderator: &str, note: &str) -> Result<BanAppeal, crate::Error> {
+        appeal.status = status;
+        appeal.reviewed_by = Some(moderator.to_string());
+        appeal.reviewed_at = Some(Utc::now());
+        appeal.moderator_note = Some(note.to_string());
+        if !self.storage()?.update_appeal(appeal.clone()).await? {
+            return Err(already_reviewed(&appeal.id))
+        }
+
+        Ok(appeal)
+    }
+}
+
+fn already_reviewed(appeal_id: &str) -> BackendError {
+    BackendError::InvalidAppeal(format!("Appeal {} was already reviewed.", appeal_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::database::history::ModerationAction;
+    use crate::database::moderation::BanDuration;
+    use crate::test_support::backend;
+    use super::*;
+
+    fn banned_backend() -> Backend {
+        let backend = backend();
+        block_on(backend.ban_player(42, BanDuration::For(Duration::days(30)), "Moderator", "Exploiting")).unwrap();
+        backend
+    }
+
+    fn assert_invalid_appeal<T: std::fmt::Debug>(result: Result<T, crate::Error>) {
+        assert!(matches!(result, Err(BackendError::InvalidAppeal(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn accepting_an_appeal_lifts_the_ban() {
+        let backend = banned_backend();
+        let appeal = block_on(backend.submit_appeal(42, "Sorry")).unwrap();
+        assert_eq!(block_on(backend.list_pending_appeals(10)).unwrap(), std::slice::from_ref(&appeal));
+
+        let accepted = block_on(backend.accept_appeal(&appeal.id, "Reviewer", "First offence")).unwrap();
+        assert_eq!(accepted.status, AppealStatus::Accepted);
+        assert_eq!(accepted.reviewed_by.as_deref(), Some("Reviewer"));
+        assert_eq!(block_on(backend.get_appeal(&appeal.id)).unwrap(), Some(accepted));
+        assert!(!block_on(backend.is_player_banned(42)).unwrap());
+        assert!(block_on(backend.list_pending_appeals(10)).unwrap().is_empty());
+
+        let last = block_on(backend.get_player_moderation_history(42)).unwrap().pop().unwrap();
+        assert_eq!(last.action, ModerationAction::Unban);
+        assert_eq!(last.moderator, "Reviewer");
+    }
+
+    #[test]
+    fn only_one_appeal_per_ban_is_pending() {
+        let backend = banned_backend();
+        block_on(backend.submit_appeal(42, "Sorry")).unwrap();
+        assert_invalid_appeal(block_on(backend.submit_appeal(42, "Sorry again")));
+
+        let result = block_on(backend.submit_appeal(43, "I am not banned"));
+        assert!(matches!(result, Err(BackendError::BanNotFound(43))), "{:?}", result);
+    }
+
+    #[test]
+    fn rejecting_an_appeal_starts_the_cooldown() {
+        let mut backend = banned_backend();
+        let appeal = block_on(backend.submit_appeal(42, "Sorry")).unwrap();
+        let rejected = block_on(backend.reject_appeal(&appeal.id, "Reviewer", "No")).unwrap();
+        assert_eq!(rejected.status, AppealStatus::Rejected);
+        assert!(block_on(backend.is_player_banned(42)).unwrap());
+
+        match block_on(backend.submit_appeal(42, "Sorry again")) {
+            Err(BackendError::AppealCooldown { retry_after }) => {
+                assert_eq!(retry_after, rejected.reviewed_at.unwrap() + Duration::days(DEFAULT_APPEAL_COOLDOWN_DAYS));
+            },
+            result => panic!("{:?}", result)
+        }
+
+        backend.set_appeal_cooldown(Duration::zero());
+        block_on(backend.submit_appeal(42, "Sorry again")).unwrap();
+    }
+
+    #[test]
+    fn reviewed_appeals_cannot_be_reviewed_again() {
+        let backend = banned_backend();
+        let appeal = block_on(backend.submit_appeal(42, "Sorry")).unwrap();
+        block_on(backend.reject_appeal(&appeal.id, "Reviewer", "No")).unwrap();
+
+        assert_invalid_appeal(block_on(backend.accept_appeal(&appeal.id, "Other reviewer", "Yes")));
+        assert_invalid_appeal(block_on(backend.reject_appeal(&appeal.id, "Other reviewer", "No")));
+        assert_invalid_appeal(block_on(backend.accept_appeal("unknown", "Other reviewer", "Yes")));
+        assert!(block_on(backend.is_player_banned(42)).unwrap());
+        assert_eq!(block_on(backend.get_appeal(&appeal.id)).unwrap().unwrap().reviewed_by.as_deref(), Some("Reviewer"));
+    }
+
+    #[test]
+    fn a_review_based_on_an_outdated_read_fails() {
+        let backend = banned_backend();
+        let appeal = block_on(backend.submit_appeal(42, "Sorry")).unwrap();
+
+        // Another moderator rejects the appeal after this one read it as pending.
+        block_on(backend.reject_appeal(&appeal.id, "Reviewer", "No")).unwrap();
+        let result = block_on(backend.close_appeal(appeal.clone(), AppealStatus::Accepted, "Other reviewer", "Yes"));
+        assert_invalid_appeal(result);
+
+        let stored = block_on(backend.get_appeal(&appeal.id)).unwrap().unwrap();
+        assert_eq!(stored.status, AppealStatus::Rejected);
+        assert!(block_on(backend.is_player_banned(42)).unwrap());
+    }
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Utc};
+
+use crate::{Backend, BackendError};
+use super::moderation::BanEntry;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BanStatus {
+    #[default]
+    Any,
+    Active,
+    Expired
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BanSortField {
+    #[default]
+    BannedTime,
+    UserId
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    #[default]
+    Descending
+}
+
+/// Filters, sorting and position for `Backend::list_bans`. Every filter left at its default matches all bans.
+#[derive(Clone, Debug, Default)]
+pub struct BanQuery {
+    pub moderator: Option<String>,
+    pub status: BanStatus,
+    pub permanent: Option<bool>,
+    /// Only bans issued at or after this time.
+    pub banned_after: Option<DateTime<Utc>>,
+    /// Only bans issued before this time.
+    pub banned_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the ban reason.
+    pub reason_contains: Option<String>,
+    pub sort_by: BanSortField,
+    pub order: SortOrder,
+    /// Defaults to `DEFAULT_PAGE_SIZE`, capped at `MAX_PAGE_SIZE`.
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>
+}
+
+/// A stored ban that could not be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidBanDocument {
+    /// The document's `_id`, if it has one.
+    pub id: Option<String>,
+    pub error: String
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BanPage {
+    pub entries: Vec<BanEntry>,
+    /// Documents in this page's range that failed to deserialize.
+    pub invalid_documents: Vec<InvalidBanDocument>,
+    /// Pass back as `BanQuery::cursor` to get the next page. `None` on the last page.
+    pub next_cursor: Option<String>
+}
+
+/// Position after the last ban of a page: its sort value, and its user ID as a tie-breaker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BanCursor {
+    pub sort_value: i64,
+    pub user_id: i64
+}
+
+impl BanCursor {
+    pub fn for_entry(entry: &BanEntry, sort_by: BanSortField) -> Self {
+        let sort_value = match sort_by {
+            BanSortField::BannedTime => entry.banned_time.timestamp_millis(),
+            BanSortField::UserId => entry.user_id
+        };
+        Self { sort_value, user_id: entry.user_id }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}:{}", self.sort_value, self.user_id)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, crate::Error> {
+        let invalid = || BackendError::InvalidQuery(format!("Invalid cursor: {}", cursor));
+        let (sort_value, user_id) = cursor.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            sort_value: sort_value.parse().map_err(|_| invalid())?,
+            user_id: user_id.parse().map_err(|_| invalid())?
+        })
+    }
+
+    /// Whether `other` comes strictly after this cursor in the given order.
+    pub fn is_before(&self, other: &BanCursor, order: SortOrder) -> bool {
+        let ordering = (other.sort_value, other.user_id).cmp(&(self.sort_value, self.user_id));
+        match order {
+            SortOrder::Ascending => ordering.is_gt(),
+            SortOrder::Descending => ordering.is_lt()
+        }
+    }
+}
+
+impl BanQuery {
+    pub fn page_size(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn decoded_cursor(&self) -> Result<Option<BanCursor>, crate::Error> {
+        self.cursor.as_deref().map(BanCursor::decode).transpose()
+    }
+
+    /// Whether the entry passes every filter. Does not look at the cursor.
+    pub fn matches(&self, entry: &BanEntry, now: DateTime<Utc>) -> bool {
+        if self.moderator.as_ref().is_some_and(|moderator| *moderator != entry.moderator) {
+            return false
+        }
+        let status_matches = match self.status {
+            BanStatus::Any => true,
+            BanStatus::Active => entry.is_active_at(now),
+            BanStatus::Expired => !entry.is_active_at(now)
+        };
+        if !status_matches {
+            return false
+        }
+        if self.permanent.is_some_and(|permanent| permanent != entry.is_permanent()) {
+            return false
+        }
+        if self.banned_after.is_some_and(|after| entry.banned_time < after) {
+            return false
+        }
+        if self.banned_before.is_some_and(|before| entry.banned_time >= before) {
+            return false
+        }
+        if let Some(text) = &self.reason_contains {
+            if !entry.reason.to_lowercase().contains(&text.to_lowercase()) {
+                return false
+            }
+        }
+        true
+    }
+}
+
+impl Backend {
+    /// One page of bans matching the query. Stored bans that fail to deserialize are listed in the page
+    /// instead of being dropped.
+    pub async fn list_bans(&self, query: BanQuery) -> Result<BanPage, crate::Error> {
+        self.storage()?.query_bans(&query, Utc::now()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use futures::executor::block_on;
+
+    use crate::test_support::backend;
+    use super::*;
+
+    fn base_time() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt((Utc::now() - Duration::days(10)).timestamp_millis()).unwrap()
+    }
+
+    fn ban(user_id: i64, banned_time: DateTime<Utc>, banned_until: Option<DateTime<Utc>>, moderator: &str, reason: &str) -> BanEntry {
+        BanEntry { user_id, banned_time, banned_until, moderator: moderator.to_string(), reason: reason.to_string() }
+    }
+
+    fn backend_with_bans(entries: &[BanEntry]) -> Backend {
+        let backend = backend();
+        for entry in entries {
+            block_on(backend.storage().unwrap().upsert_ban(entry.clone())).unwrap();
+        }
+        backend
+    }
+
+    /// Bans sharing their `banned_time` in groups, so pages have to break ties on the user ID.
+    fn tied_bans() -> Vec<BanEntry> {
+        let base = base_time();
+        [(5, 0), (3, 0), (9, 0), (1, 1), (8, 1), (2, 2), (7, 2)]
+            .into_iter()
+            .map(|(user_id, hours)| ban(user_id, base + Duration::hours(hours), None, "Moderator", "Exploiting"))
+            .collect()
+    }
+
+    fn all_pages(backend: &Backend, query: BanQuery) -> Vec<i64> {
+        let mut user_ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = block_on(backend.list_bans(BanQuery { cursor, ..query.clone() })).unwrap();
+            assert!(page.entries.len() <= query.page_size() as usize);
+            user_ids.extend(page.entries.iter().map(|entry| entry.user_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return user_ids
+            }
+        }
+    }
+
+    fn matching(backend: &Backend, query: BanQuery) -> Vec<i64> {
+        let mut user_ids = all_pages(backend, BanQuery { sort_by: BanSortField::UserId, order: SortOrder::Ascending, ..query });
+        user_ids.sort();
+        user_ids
+    }
+
+    #[test]
+    fn pages_through_tied_ban_times_in_both_orders() {
+        let backend = backend_with_bans(&tied_bans());
+        let ascending = vec![3, 5, 9, 1, 8, 2, 7];
+        let descending: Vec<i64> = ascending.iter().rev().copied().collect();
+
+        for limit in [1, 2, 3, 7, 8] {
+            let query = BanQuery { order: SortOrder::Ascending, limit: Some(limit), ..BanQuery::default() };
+            assert_eq!(all_pages(&backend, query), ascending, "ascending, {} per page", limit);
+            let query = BanQuery { order: SortOrder::Descending, limit: Some(limit), ..BanQuery::default() };
+            assert_eq!(all_pages(&backend, query), descending, "descending, {} per page", limit);
+        }
+    }
+
+    #[test]
+    fn sorts_by_user_id_in_both_orders() {
+        let backend = backend_with_bans(&tied_bans());
+        let query = BanQuery { sort_by: BanSortField::UserId, order: SortOrder::Ascending, limit: Some(3), ..BanQuery::default() };
+        assert_eq!(all_pages(&backend, query), vec![1, 2, 3, 5, 7, 8, 9]);
+        let query = BanQuery { sort_by: BanSortField::UserId, limit: Some(3), ..BanQuery::default() };
+        assert_eq!(all_pages(&backend, query), vec![9, 8, 7, 5, 3, 2, 1]);
+    }
+
+    #[test]
+    fn only_pages_with_more_bans_have_a_cursor() {
+        let backend = backend_with_bans(&tied_bans());
+        let first = block_on(backend.list_bans(BanQuery { limit: Some(4), ..BanQuery::default() })).unwrap();
+        assert_eq!(first.entries.len(), 4);
+        let cursor = first.next_cursor.clone().unwrap();
+        assert_eq!(BanCursor::decode(&cursor).unwrap(), BanCursor::for_entry(first.entries.last().unwrap(), BanSortField::BannedTime));
+
+        let last = block_on(backend.list_bans(BanQuery { limit: Some(4), cursor: Some(cursor), ..BanQuery::default() })).unwrap();
+        assert_eq!(last.entries.len(), 3);
+        assert_eq!(last.next_cursor, None);
+
+        let exact = block_on(backend.list_bans(BanQuery { limit: Some(7), ..BanQuery::default() })).unwrap();
+        assert_eq!(exact.entries.len(), 7);
+        assert_eq!(exact.next_cursor, None);
+
+        let result = block_on(backend.list_bans(BanQuery { cursor: Some("yesterday".to_string()), ..BanQuery::default() }));
+        assert!(matches!(result, Err(BackendError::InvalidQuery(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn applies_every_filter() {
+        let base = base_time();
+        let backend = backend_with_bans(&[
+            ban(1, base, None, "Alice", "Exploiting the lobby"),
+            ban(2, base + Duration::days(1), Some(base + Duration::days(2)), "Bob", "Spam"),
+            ban(3, base + Duration::days(2), Some(base + Duration::days(30)), "Alice", "EXPLOITING"),
+            ban(4, base + Duration::days(3), Some(base + Duration::days(4)), "Alice", "Harassment")
+        ]);
+
+        assert_eq!(matching(&backend, BanQuery::default()), vec![1, 2, 3, 4]);
+        assert_eq!(matching(&backend, BanQuery { moderator: Some("Alice".to_string()), ..BanQuery::default() }), vec![1, 3, 4]);
+        assert_eq!(matching(&backend, BanQuery { status: BanStatus::Active, ..BanQuery::default() }), vec![1, 3]);
+        assert_eq!(matching(&backend, BanQuery { status: BanStatus::Expired, ..BanQuery::default() }), vec![2, 4]);
+        assert_eq!(matching(&backend, BanQuery { permanent: Some(true), ..BanQuery::default() }), vec![1]);
+        assert_eq!(matching(&backend, BanQuery { permanent: Some(false), ..BanQuery::default() }), vec![2, 3, 4]);
+        assert_eq!(matching(&backend, BanQuery { banned_after: Some(base + Duration::days(1)), ..BanQuery::default() }), vec![2, 3, 4]);
+        assert_eq!(matching(&backend, BanQuery { banned_before: Some(base + Duration::days(2)), ..BanQuery::default() }), vec![1, 2]);
+        assert_eq!(matching(&backend, BanQuery { reason_contains: Some("exploit".to_string()), ..BanQuery::default() }), vec![1, 3]);
+
+        let combined = BanQuery {
+            moderator: Some("Alice".to_string()),
+            status: BanStatus::Active,
+            permanent: Some(false),
+            banned_after: Some(base),
+            banned_before: Some(base + Duration::days(3)),
+            reason_contains: Some("Exploiting".to_string()),
+            ..BanQuery::default()
+        };
+        assert_eq!(matching(&backend, combined), vec![3]);
+    }
+
+    #[test]
+    fn clamps_the_page_size() {
+        assert_eq!(BanQuery::default().page_size(), DEFAULT_PAGE_SIZE);
+        assert_eq!(BanQuery { limit: Some(0), ..BanQuery::default() }.page_size(), 1);
+        assert_eq!(BanQuery { limit: Some(MAX_PAGE_SIZE + 1), ..BanQuery::default() }.page_size(), MAX_PAGE_SIZE);
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+
+use crate::Backend;
+use super::history::{ModerationAction, ModerationRecord};
+use super::moderation::{BanDuration, BanEntry};
+
+/// What happened to one player of a bulk ban or unban.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BulkOutcome {
+    /// `previous` is the ban that was replaced, if the player was already banned.
+    Banned { previous: Option<BanEntry> },
+    Unbanned { previous: BanEntry },
+    /// The player had no ban to lift.
+    NotBanned,
+    /// The user ID was already listed earlier in the same request.
+    Duplicate,
+    /// The entry could not be written. Holds the storage error.
+    Failed(String)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BulkResult {
+    pub user_id: u64,
+    pub outcome: BulkOutcome
+}
+
+fn dedup_user_ids(user_ids: &[u64]) -> (Vec<u64>, HashSet<usize>) {
+    let mut seen = HashSet::new();
+    let mut unique = Vec::new();
+    let mut duplicates = HashSet::new();
+    for (index, user_id) in user_ids.iter().enumerate() {
+        if seen.insert(*user_id) {
+            unique.push(*user_id);
+        } else {
+            duplicates.insert(index);
+        }
+    }
+    (unique, duplicates)
+}
+
+fn history_record(user_id: i64, action: ModerationAction, moderator: &str, reason: &str, previous: Option<BanEntry>, current: Option<BanEntry>) -> ModerationRecord {
+    ModerationRecord {
+        user_id,
+        action,
+        moderator: moderator.to_string(),
+        reason: reason.to_string(),
+        time: Utc::now(),
+        previous,
+        current,
+        restriction: None
+    }
+}
+
+impl Backend {
+    /// Bans every listed player with the same duration and reason, writing all bans in one batch.
+    /// Results are in the order of `user_ids`.
+    pub async fn bulk_ban_players(&self, user_ids: &[u64], duration: BanDuration, moderator: &str, reason: &str) -> Result<Vec<BulkResult>, crate::Error> {
+        let time_now = Utc::now();
+        let banned_until = duration.ends_at(time_now)?;
+        let (unique, duplicates) = dedup_user_ids(user_ids);
+
+        let mut previous: HashMap<i64, BanEntry> = self.storage()?.find_bans(&unique).await?
+            .into_iter()
+            .map(|entry| (entry.user_id, entry))
+            .collect();
+        let entries: Vec<BanEntry> = unique.iter()
+            .map(|user_id| BanEntry {
+                user_id: *user_id as i64,
+                banned_time: time_now,
+                banned_until,
+                moderator: moderator.to_string(),
+                reason: reason.to_string()
+            })
+            .collect();
+        let failures: HashMap<i64, String> = self.storage()?.upsert_bans(entries.clone()).await?.into_iter().collect();
+
+        let records: Vec<ModerationRecord> = entries.into_iter()
+            .filter(|entry| !failures.contains_key(&entry.user_id))
+            .map(|entry| history_record(entry.user_id, ModerationAction::Ban, moderator, reason, previous.get(&entry.user_id).cloned(), Some(entry)))
+            .collect();
+        if !records.is_empty() {
+            self.storage()?.append_history_batch(records).await?;
+        }
+
+        Ok(user_ids.iter().enumerate().map(|(index, user_id)| {
+            let key = *user_id as i64;
+            let outcome = if duplicates.contains(&index) {
+                BulkOutcome::Duplicate
+            } else if let Some(error) = failures.get(&key) {
+                BulkOutcome::Failed(error.clone())
+            } else {
+                BulkOutcome::Banned { previous: previous.remove(&key) }
+            };
+            BulkResult { user_id: *user_id, outcome }
+        }).collect())
+    }
+
+    /// Lifts the bans of every listed player in one batch. Results are in the order of `user_ids`.
+    pub async fn bulk_unban_players(&self, user_ids: &[u64], moderator: &str, reason: &str) -> Result<Vec<BulkResult>, crate::Error> {
+        let (unique, duplicates) = dedup_user_ids(user_ids);
+
+        let mut found: HashMap<i64, BanEntry> = self.storage()?.find_bans(&unique).await?
+            .into_iter()
+            .map(|entry| (entry.user_id, entry))
+            .collect();
+        if !found.is_empty() {
+            let banned: Vec<u64> = found.keys().map(|user_id| *user_id as u64).collect();
+            self.storage()?.delete_bans(&banned).await?;
+
+            let records: Vec<ModerationRecord> = found.values()
+                .map(|entry| history_record(entry.user_id, ModerationAction::Unban, moderator, reason, Some(entry.clone()), None))
+                .collect();
+            self.storage()?.append_history_batch(records).await?;
+        }
+
+        Ok(user_ids.iter().enumerate().map(|(index, user_id)| {
+            let outcome = if duplicates.contains(&index) {
+                BulkOutcome::Duplicate
+            } else {
+                match found.remove(&(*user_id as i64)) {
+                    Some(previous) => BulkOutcome::Unbanned { previous },
+                    None => BulkOutcome::NotBanned
+                }
+            };
+            BulkResult { user_id: *user_id, outcome }
+        }).collect())
+    }
+}
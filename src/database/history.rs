@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use serde::{ Deserialize, Serialize };
+
+use crate::Backend;
+use super::moderation::BanEntry;
+use super::restrictions::Restriction;
+
+/// Moderator name recorded for actions the backend takes on its own, like expiring bans.
+pub const SYSTEM_MODERATOR: &str = "System";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModerationAction {
+    Ban,
+    Unban,
+    Edit,
+    Expire,
+    Warn,
+    Kick,
+    Restrict,
+    Unrestrict
+}
+
+/// One entry of the moderation audit trail. `previous` and `current` hold the ban before and after the action,
+/// `restriction` the restriction that was applied or lifted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModerationRecord {
+    #[serde(rename = "userId")]
+    pub user_id: i64,
+    pub action: ModerationAction,
+    pub moderator: String,
+    pub reason: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    pub previous: Option<BanEntry>,
+    pub current: Option<BanEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restriction: Option<Restriction>
+}
+
+impl Backend {
+    pub(crate) async fn record_moderation_action(&self, user_id: i64, action: ModerationAction, moderator: &str, reason: &str, previous: Option<BanEntry>, current: Option<BanEntry>) -> Result<(), crate::Error> {
+        self.storage()?.append_history(ModerationRecord {
+            user_id,
+            action,
+            moderator: moderator.to_string(),
+            reason: reason.to_string(),
+            time: Utc::now(),
+            previous,
+            current,
+            restriction: None
+        }).await
+    }
+
+    pub(crate) async fn record_restriction_action(&self, action: ModerationAction, moderator: &str, reason: &str, restriction: Restriction) -> Result<(), crate::Error> {
+        self.storage()?.append_history(ModerationRecord {
+            user_id: restriction.user_id,
+            action,
+            moderator: moderator.to_string(),
+            reason: reason.to_string(),
+            time: Utc::now(),
+            previous: None,
+            current: None,
+            restriction: Some(restriction)
+        }).await
+    }
+
+    /// Every recorded action against the player, oldest first.
+    pub async fn get_player_moderation_history(&self, user_id: u64) -> Result<Vec<ModerationRecord>, crate::Error> {
+        self.storage()?.history_for_user(user_id).await
+    }
+
+    /// The moderator's most recent actions, newest first.
+    pub async fn get_moderator_actions(&self, moderator: &str, limit: u32) -> Result<Vec<ModerationRecord>, crate::Error> {
+        self.storage()?.history_for_moderator(moderator, limit).await
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, Utc};
+use serde::{ Deserialize, Serialize };
+
+use crate::{Backend, BackendError};
+use super::moderation::BanEntry;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkSource {
+    /// Both accounts own an API key associated with the same Discord user.
+    Discord,
+    /// Linked by a moderator.
+    Manual
+}
+
+/// A moderator-recorded link between two accounts. Stored with the lower user ID first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccountLink {
+    #[serde(rename = "userId")]
+    pub user_id: i64,
+    #[serde(rename = "linkedUserId")]
+    pub linked_user_id: i64,
+    pub moderator: String,
+    pub reason: String,
+    #[serde(rename = "createdAt", with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>
+}
+
+impl AccountLink {
+    /// The account on the other side of the link from `user_id`.
+    pub fn other(&self, user_id: u64) -> u64 {
+        if self.user_id == user_id as i64 {
+            self.linked_user_id as u64
+        } else {
+            self.user_id as u64
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkedAccount {
+    pub user_id: u64,
+    pub source: LinkSource
+}
+
+/// The ban that made a ban check match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BanMatch {
+    pub ban: BanEntry,
+    /// The linked account the ban belongs to, `None` if it is the player's own ban.
+    pub linked_account: Option<LinkedAccount>
+}
+
+impl Backend {
+    /// Records that both accounts belong to the same person. Linking an already linked pair updates the link.
+    pub async fn link_accounts(&self, user_id: u64, linked_user_id: u64, moderator: &str, reason: &str) -> Result<AccountLink, crate::Error> {
+        if user_id == linked_user_id {
+            return Err(BackendError::InvalidAccountLink("An account cannot be linked to itself.".to_string()))
+        }
+
+        let link = AccountLink {
+            user_id: user_id.min(linked_user_id) as i64,
+            linked_user_id: user_id.max(linked_user_id) as i64,
+            moderator: moderator.to_string(),
+            reason: reason.to_string(),
+            created_at: Utc::now()
+        };
+        self.storage()?.upsert_link(link.clone()).await?;
+        Ok(link)
+    }
+
+    /// Removes a manual link. Returns whether there was one.
+    pub async fn unlink_accounts(&self, user_id: u64, linked_user_id: u64) -> Result<bool, crate::Error> {
+        self.storage()?.delete_link(user_id.min(linked_user_id), user_id.max(linked_user_id)).await
+    }
+
+    /// Accounts directly linked to the player, manually or through a shared Discord user. Links are not
+    /// followed transitively.
+    pub async fn get_linked_accounts(&self, user_id: u64) -> Result<Vec<LinkedAccount>, crate::Error> {
+        let mut linked: Vec<LinkedAccount> = self.storage()?.links_for_user(user_id).await?
+            .iter()
+            .map(|link| LinkedAccount { user_id: link.other(user_id), source: LinkSource::Manual })
+            .collect();
+
+        // The player may own several keys, each associated with a different Discord user.
+        let mut discord_users: Vec<String> = self.storage()?.api_keys_for_owner(&user_id.to_string()).await?
+            .into_iter()
+            .filter_map(|key| key.associated_discord_user)
+            .collect();
+        discord_users.sort();
+        discord_users.dedup();
+        for discord_user in discord_users {
+            for key in self.storage()?.api_keys_for_discord_user(&discord_user).await? {
+                let owner = match key.assign_owner.parse::<u64>() {
+                    Ok(owner) => owner,
+                    Err(_) => continue
+                };
+                if owner != user_id && !linked.iter().any(|account| account.user_id == owner) {
+                    linked.push(LinkedAccount { user_id: owner, source: LinkSource::Discord });
+                }
+            }
+        }
+
+        Ok(linked)
+    }
+
+    /// The active ban that applies to the player: their own, or with `include_linked` the first active ban
+    /// among their linked accounts.
+    pub async fn check_ban(&self, user_id: u64, include_linked: bool) -> Result<Option<BanMatch>, crate::Error> {
+        if let Some(ban) = self.get_active_ban(user_id).await? {
+            return Ok(Some(BanMatch { ban, linked_account: None }))
+        }
+        if !include_linked {
+            return Ok(None)
+        }
+
+        let linked = self.get_linked_accounts(user_id).await?;
+        if linked.is_empty() {
+            return Ok(None)
+        }
+        let linked_ids: Vec<u64> = linked.iter().map(|account| account.user_id).collect();
+        let bans = self.storage()?.find_bans(&linked_ids).await?;
+
+        Ok(linked.into_iter().find_map(|account| {
+            bans.iter()
+                .find(|ban| ban.user_id == account.user_id as i64 && ban.is_active())
+                .map(|ban| BanMatch { ban: ban.clone(), linked_account: Some(account) })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use futures::executor::block_on;
+
+    use crate::database::api_keys::{ApiKeyOwner, ApiScope};
+    use crate::database::moderation::BanDuration;
+    use crate::test_support::backend;
+    use super::*;
+
+    fn create_key(backend: &Backend, roblox_id: u64, discord_id: u64) {
+        let owner = ApiKeyOwner { roblox_id: Some(roblox_id), discord_id: Some(discord_id) };
+        block_on(backend.create_api_key(owner, vec![ApiScope::BansRead], None)).unwrap();
+    }
+
+    #[test]
+    fn links_accounts_through_every_owned_key() {
+        let backend = backend();
+        create_key(&backend, 1, 100);
+        create_key(&backend, 1, 200);
+        create_key(&backend, 2, 100);
+        create_key(&backend, 3, 200);
+        create_key(&backend, 4, 300);
+        block_on(backend.link_accounts(5, 1, "Moderator", "Alt account")).unwrap();
+
+        let mut linked = block_on(backend.get_linked_accounts(1)).unwrap();
+        linked.sort_by_key(|account| account.user_id);
+        assert_eq!(linked, vec![
+            LinkedAccount { user_id: 2, source: LinkSource::Discord },
+            LinkedAccount { user_id: 3, source: LinkSource::Discord },
+            LinkedAccount { user_id: 5, source: LinkSource::Manual }
+        ]);
+        assert_eq!(block_on(backend.get_linked_accounts(3)).unwrap(), vec![LinkedAccount { user_id: 1, source: LinkSource::Discord }]);
+    }
+
+    #[test]
+    fn checks_bans_of_accounts_linked_through_a_second_key() {
+        let backend = backend();
+        create_key(&backend, 1, 100);
+        create_key(&backend, 1, 200);
+        create_key(&backend, 3, 200);
+        block_on(backend.ban_player(3, BanDuration::For(Duration::days(1)), "Moderator", "Exploiting")).unwrap();
+
+        assert_eq!(block_on(backend.check_ban(1, false)).unwrap(), None);
+        let ban_match = block_on(backend.check_ban(1, true)).unwrap().unwrap();
+        assert_eq!(ban_match.ban.user_id, 3);
+        assert_eq!(ban_match.linked_account, Some(LinkedAccount { user_id: 3, source: LinkSource::Discord }));
+    }
+}
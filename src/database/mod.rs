@@ -1,15 +1,40 @@
+use std::sync::Arc;
+
 use mongodb::Database;
 
-use crate::Backend;
+use crate::{Backend, BackendError};
 
 pub mod api_keys;
+pub mod appeals;
+pub mod ban_query;
+pub mod bulk;
+pub mod history;
+pub mod links;
 pub mod moderation;
+pub mod rate_limit;
+pub mod restrictions;
+pub mod storage;
+pub mod transfer;
+
+use storage::{MemoryStore, Storage};
 
 impl Backend {
-    pub fn get_database(&self) -> Database {
-        if Option::is_none(&self.mongo_client) {
-            panic!("Database not connected!")
-        }
-        self.mongo_client.as_ref().unwrap().default_database().unwrap()
+    pub fn get_database(&self) -> Result<Database, crate::Error> {
+        self.mongo_client
+            .as_ref()
+            .and_then(|client| client.default_database())
+            .ok_or(BackendError::DatabaseNotConnected)
+    }
+
+    pub fn set_storage<S: Storage + 'static>(&mut self, storage: S) {
+        self.storage = Some(Arc::new(storage));
+    }
+
+    pub fn use_in_memory_storage(&mut self) {
+        self.set_storage(MemoryStore::new());
+    }
+
+    pub(crate) fn storage(&self) -> Result<&dyn Storage, crate::Error> {
+        self.storage.as_deref().ok_or(BackendError::DatabaseNotConnected)
     }
-}
\ No newline at end of file
+}
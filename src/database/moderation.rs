@@ -1,100 +1,348 @@
-use mongodb::{bson::doc, Collection};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use serde::{ Deserialize, Serialize };
-use futures::stream::StreamExt;
 
-use crate::Backend;
-use crate::utils::datetime_now;
+use crate::{Backend, BackendError};
+use super::ban_query::BanPage;
+use super::history::{ModerationAction, ModerationRecord, SYSTEM_MODERATOR};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BanEntry {
+/// Version written to `schemaVersion`. Documents without it were written by the old `ban_player`, which
+/// added the duration to `bannedUntil` in seconds instead of milliseconds.
+pub const BAN_SCHEMA_VERSION: u32 = 1;
+const PERMANENT_BAN: i64 = -1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BanDuration {
+    Permanent,
+    For(Duration),
+    Until(DateTime<Utc>)
+}
+
+impl BanDuration {
+    /// When a ban starting at `start` ends, `None` meaning never.
+    pub fn ends_at(&self, start: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, crate::Error> {
+        let ends_at = match self {
+            Self::Permanent => return Ok(None),
+            Self::For(duration) => {
+                if *duration <= Duration::zero() {
+                    return Err(BackendError::InvalidBanDuration("Ban duration must be positive.".to_string()))
+                }
+                start.checked_add_signed(*duration)
+                    .ok_or_else(|| BackendError::InvalidBanDuration("Ban duration is too long.".to_string()))?
+            },
+            Self::Until(until) => *until
+        };
+
+        if ends_at <= start {
+            return Err(BackendError::InvalidBanDuration("Ban must end in the future.".to_string()))
+        }
+        Ok(Some(ends_at))
+    }
+}
+
+/// A ban as stored: times in milliseconds since the Unix epoch, `bannedUntil` of -1 for permanent bans.
+#[derive(Serialize, Deserialize)]
+struct BanDocument {
     #[serde(rename = "userId")]
-    pub user_id: i64,
+    user_id: i64,
     #[serde(rename = "bannedTime")]
-    pub banned_time: i64,
+    banned_time: i64,
     #[serde(rename = "bannedUntil")]
-    pub banned_until: i64,
+    banned_until: i64,
+    moderator: String,
+    reason: String,
+    #[serde(rename = "schemaVersion", default, skip_serializing_if = "Option::is_none")]
+    schema_version: Option<u32>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "BanDocument", into = "BanDocument")]
+pub struct BanEntry {
+    pub user_id: i64,
+    pub banned_time: DateTime<Utc>,
+    /// `None` for permanent bans.
+    pub banned_until: Option<DateTime<Utc>>,
     pub moderator: String,
     pub reason: String
 }
 
-impl Backend {
-    // Note: + Send because #[OpenApi] complain about not being able to send between threads safely
-    pub async fn get_ban_collection(&self) -> Result<Vec<BanEntry>, crate::Error> {
-        let database = self.get_database();
+fn from_millis(millis: i64) -> Result<DateTime<Utc>, String> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| format!("Timestamp {} is out of range.", millis))
+}
 
-        let collection: Collection<BanEntry> = database.collection("bannedplayers");
+impl TryFrom<BanDocument> for BanEntry {
+    type Error = String;
 
-        let mut cursor = collection.find(None, None).await?;
-        let mut result: Vec<BanEntry> = Vec::new();
+    fn try_from(document: BanDocument) -> Result<Self, Self::Error> {
+        let banned_until = match document.banned_until {
+            PERMANENT_BAN => None,
+            // `minutes * 60` was computed as an `i32`, which only wraps below zero for bans of 68 years or more.
+            banned_until if document.schema_version.is_none() && banned_until < document.banned_time => None,
+            banned_until if document.schema_version.is_none() => {
+                // Legacy entries hold `bannedTime + minutes * 60`, recover the minutes and redo it in milliseconds.
+                let minutes = (banned_until - document.banned_time) / 60;
+                Some(from_millis(document.banned_time.saturating_add(minutes.saturating_mul(60_000)))?)
+            },
+            banned_until => Some(from_millis(banned_until)?)
+        };
 
-        while let Some(stream) = cursor.next().await {
-            match stream {
-                Ok(document) => {
-                    result.push(document);
-                }
-                _ => {}
-            }
+        Ok(Self {
+            user_id: document.user_id,
+            banned_time: from_millis(document.banned_time)?,
+            banned_until,
+            moderator: document.moderator,
+            reason: document.reason
+        })
+    }
+}
+
+impl From<BanEntry> for BanDocument {
+    fn from(entry: BanEntry) -> Self {
+        Self {
+            user_id: entry.user_id,
+            banned_time: entry.banned_time.timestamp_millis(),
+            banned_until: entry.banned_until.map_or(PERMANENT_BAN, |until| until.timestamp_millis()),
+            moderator: entry.moderator,
+            reason: entry.reason,
+            schema_version: Some(BAN_SCHEMA_VERSION)
         }
+    }
+}
 
-        Ok(result)
+/// Changes to an existing ban. Fields left as `None` keep their current value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BanEdit {
+    /// Counted from the original ban time.
+    pub duration: Option<BanDuration>,
+    pub reason: Option<String>
+}
+
+impl BanEntry {
+    pub fn is_permanent(&self) -> bool {
+        self.banned_until.is_none()
     }
 
-    pub(crate) async fn find_ban_entry(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
-        let database = self.get_database();
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.banned_until.is_none_or(|until| until > now)
+    }
 
-        let collection: Collection<BanEntry> = database.collection("bannedplayers");
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(Utc::now())
+    }
+}
 
-        let result = collection.find_one(
-            doc! { 
-                "userId": user_id as i64
-            },
-            None
-        ).await?;
+impl Backend {
+    // Note: + Send because #[OpenApi] complain about not being able to send between threads safely
+    /// Every ban. Stored bans that cannot be read are reported in `invalid_documents` instead of failing the call.
+    pub async fn get_ban_collection(&self) -> Result<BanPage, crate::Error> {
+        self.storage()?.list_bans().await
+    }
 
-        Ok(result)
+    pub(crate) async fn find_ban_entry(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
+        self.storage()?.find_ban(user_id).await
     }
 
-    pub async fn ban_player(&self, user_id: u64, duration_in_minutes: i32, moderator: &str, reason: &str) -> Result<(), crate::Error> {
-        let database = self.get_database();
+    /// The player's ban, unless there is none or it has expired.
+    pub async fn get_active_ban(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
+        let ban_entry = self.find_ban_entry(user_id).await?;
+        Ok(ban_entry.filter(|entry| entry.is_active()))
+    }
 
-        let collection: Collection<BanEntry> = database.collection("bannedplayers");
+    pub async fn is_player_banned(&self, user_id: u64) -> Result<bool, crate::Error> {
+        Ok(self.get_active_ban(user_id).await?.is_some())
+    }
 
-        let time_now: i64 = datetime_now() as i64;
-        let banned_until = if duration_in_minutes != -1 { time_now as i64 + (duration_in_minutes * 60) as i64} else { -1 };
-        if self.find_ban_entry(user_id).await?.is_some() {
-            let update = doc! { "$set": doc! {
-                "bannedTime": time_now,
-                "bannedUntil": banned_until,
-                "moderator": moderator.to_string(),
-                "reason": reason.to_string()
-            } };
+    /// Removes every ban that has run out, recording each in the moderation history. Returns how many were removed.
+    pub async fn sweep_expired_bans(&self) -> Result<u64, crate::Error> {
+        let time_now = Utc::now();
+        let expired = self.storage()?.delete_expired_bans(time_now).await?;
+        let removed = expired.len() as u64;
 
-            collection.update_one(doc! { 
-                "userId": user_id as i64
-            }, update, None).await?;
-        } else {
-            collection.insert_one(BanEntry {
-                user_id: user_id as i64,
-                banned_time: time_now,
-                banned_until: banned_until,
-                moderator: moderator.to_string(),
-                reason: reason.to_string()
-            }, None).await?;
-        }
+        let records = expired.into_iter()
+            .map(|entry| ModerationRecord {
+                user_id: entry.user_id,
+                action: ModerationAction::Expire,
+                moderator: SYSTEM_MODERATOR.to_string(),
+                reason: "Ban expired.".to_string(),
+                time: time_now,
+                previous: Some(entry),
+                current: None,
+                restriction: None
+            })
+            .collect();
+        self.storage()?.append_history_batch(records).await?;
 
-        Ok(())
+        Ok(removed)
+    }
+
+    /// Rewrites bans stored by older versions in the current format. Legacy entries are already corrected
+    /// when read, so this only needs to run once per database. Returns how many entries were rewritten.
+    pub async fn migrate_legacy_bans(&self) -> Result<u64, crate::Error> {
+        self.storage()?.migrate_legacy_bans().await
     }
 
-    pub async fn unban_player(&self, user_id: u64) -> Result<(), crate::Error> {
-        let database = self.get_database();
+    pub async fn ban_player(&self, user_id: u64, duration: BanDuration, moderator: &str, reason: &str) -> Result<(), crate::Error> {
+        let time_now = Utc::now();
+        let banned_until = duration.ends_at(time_now)?;
+
+        let previous = self.find_ban_entry(user_id).await?;
+        let entry = BanEntry {
+            user_id: user_id as i64,
+            banned_time: time_now,
+            banned_until,
+            moderator: moderator.to_string(),
+            reason: reason.to_string()
+        };
+        self.storage()?.upsert_ban(entry.clone()).await?;
+
+        self.record_moderation_action(entry.user_id, ModerationAction::Ban, moderator, reason, previous, Some(entry)).await
+    }
 
-        let collection: Collection<BanEntry> = database.collection("bannedplayers");
+    /// Changes the duration or reason of an existing ban. The original moderator stays on the ban, `moderator`
+    /// and `reason` only go into the history.
+    pub async fn edit_ban(&self, user_id: u64, edit: BanEdit, moderator: &str, reason: &str) -> Result<BanEntry, crate::Error> {
+        let previous = self.find_ban_entry(user_id).await?.ok_or(BackendError::BanNotFound(user_id))?;
+
+        let mut entry = previous.clone();
+        if let Some(duration) = edit.duration {
+            entry.banned_until = duration.ends_at(entry.banned_time)?;
+        }
+        if let Some(ban_reason) = edit.reason {
+            entry.reason = ban_reason;
+        }
+        self.storage()?.upsert_ban(entry.clone()).await?;
+
+        self.record_moderation_action(entry.user_id, ModerationAction::Edit, moderator, reason, Some(previous), Some(entry.clone())).await?;
+        Ok(entry)
+    }
 
+    pub async fn unban_player(&self, user_id: u64, moderator: &str, reason: &str) -> Result<(), crate::Error> {
         let found = self.find_ban_entry(user_id).await?;
         if found.is_some() {
-            collection.delete_one(doc! { "userId": user_id as i64}, None).await?;
+            self.storage()?.delete_ban(user_id).await?;
+            self.record_moderation_action(user_id as i64, ModerationAction::Unban, moderator, reason, found, None).await?;
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use mongodb::bson::{self, doc};
+
+    use crate::test_support::backend;
+    use super::*;
+
+    fn ban(user_id: i64, banned_until: Option<DateTime<Utc>>) -> BanEntry {
+        BanEntry {
+            user_id,
+            banned_time: Utc::now() - Duration::days(2),
+            banned_until,
+            moderator: "Moderator".to_string(),
+            reason: "Exploiting".to_string()
+        }
+    }
+
+    /// A ban as the old `ban_player` stored it, `bannedTime` in milliseconds and the duration in seconds.
+    fn legacy_document(banned_time: i64, duration_in_minutes: i32) -> mongodb::bson::Document {
+        let banned_until = if duration_in_minutes != -1 {
+            banned_time + duration_in_minutes.wrapping_mul(60) as i64
+        } else {
+            -1
+        };
+        doc! {
+            "userId": 42_i64,
+            "bannedTime": banned_time,
+            "bannedUntil": banned_until,
+            "moderator": "Moderator",
+            "reason": "Exploiting"
+        }
+    }
+
+    fn read(document: mongodb::bson::Document) -> BanEntry {
+        bson::from_document(document).unwrap()
+    }
+
+    #[test]
+    fn corrects_legacy_ban_durations() {
+        let banned_time = 1_700_000_000_000;
+        let start = from_millis(banned_time).unwrap();
+
+        assert_eq!(read(legacy_document(banned_time, -1)).banned_until, None);
+        assert_eq!(read(legacy_document(banned_time, 60)).banned_until, Some(start + Duration::minutes(60)));
+        assert_eq!(read(legacy_document(banned_time, 30 * 24 * 60)).banned_until, Some(start + Duration::days(30)));
+
+        // 40 million minutes times 60 wraps to a negative i32, the ban was meant to last about 76 years.
+        let wrapped = read(legacy_document(banned_time, 40_000_000));
+        assert!(wrapped.is_permanent());
+        assert!(wrapped.is_active_at(start + Duration::days(365 * 100)));
+    }
+
+    #[test]
+    fn leaves_current_ban_documents_as_they_are() {
+        let entry = BanEntry {
+            user_id: 42,
+            banned_time: from_millis(1_700_000_000_000).unwrap(),
+            banned_until: Some(from_millis(1_700_003_600_000).unwrap()),
+            moderator: "Moderator".to_string(),
+            reason: "Exploiting".to_string()
+        };
+        let document = bson::to_document(&entry).unwrap();
+        assert_eq!(document.get_i64("bannedUntil"), Ok(1_700_003_600_000));
+        assert!(document.contains_key("schemaVersion"));
+        assert_eq!(read(document), entry);
+
+        let permanent = BanEntry { banned_until: None, ..entry };
+        assert_eq!(bson::to_document(&permanent).unwrap().get_i64("bannedUntil"), Ok(-1));
+    }
+
+    #[test]
+    fn sweeps_only_expired_bans() {
+        let backend = backend();
+        let storage = backend.storage().unwrap();
+        block_on(storage.upsert_ban(ban(1, Some(Utc::now() - Duration::days(1))))).unwrap();
+        block_on(storage.upsert_ban(ban(2, Some(Utc::now() - Duration::minutes(1))))).unwrap();
+        block_on(storage.upsert_ban(ban(3, Some(Utc::now() + Duration::days(1))))).unwrap();
+        block_on(storage.upsert_ban(ban(4, None))).unwrap();
+
+        assert!(!block_on(backend.is_player_banned(1)).unwrap());
+        assert_eq!(block_on(backend.sweep_expired_bans()).unwrap(), 2);
+        assert_eq!(block_on(backend.sweep_expired_bans()).unwrap(), 0);
+
+        for user_id in [1, 2] {
+            assert!(block_on(backend.find_ban_entry(user_id)).unwrap().is_none());
+            let history = block_on(backend.get_player_moderation_history(user_id)).unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].action, ModerationAction::Expire);
+            assert_eq!(history[0].moderator, SYSTEM_MODERATOR);
+            assert_eq!(history[0].previous.as_ref().map(|entry| entry.user_id), Some(user_id as i64));
+        }
+        assert!(block_on(backend.is_player_banned(3)).unwrap());
+        assert!(block_on(backend.is_player_banned(4)).unwrap());
+    }
+
+    #[test]
+    fn bans_and_unbans_players() {
+        let backend = backend();
+        block_on(backend.ban_player(42, BanDuration::Permanent, "Moderator", "Exploiting")).unwrap();
+
+        assert!(block_on(backend.is_player_banned(42)).unwrap());
+        assert!(!block_on(backend.is_player_banned(43)).unwrap());
+        let ban = block_on(backend.get_active_ban(42)).unwrap().unwrap();
+        assert_eq!(ban.moderator, "Moderator");
+        assert_eq!(ban.reason, "Exploiting");
+        assert!(ban.is_permanent());
+
+        block_on(backend.unban_player(42, "Moderator", "Appealed")).unwrap();
+        assert!(!block_on(backend.is_player_banned(42)).unwrap());
+
+        let actions: Vec<ModerationAction> = block_on(backend.get_player_moderation_history(42)).unwrap()
+            .into_iter()
+            .map(|record| record.action)
+            .collect();
+        assert_eq!(actions, [ModerationAction::Ban, ModerationAction::Unban]);
+    }
+}
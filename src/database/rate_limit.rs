@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{ Deserialize, Serialize };
+
+use crate::{Backend, BackendError};
+
+/// Past this many tracked keys, buckets that have refilled completely are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A token bucket: up to `burst` requests at once, refilled at `per_minute` requests per minute.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    #[serde(rename = "perMinute")]
+    pub per_minute: u32
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self { burst: 60, per_minute: 60 }
+    }
+}
+
+impl RateLimit {
+    fn refill_per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    /// Whole requests left in the bucket after this one.
+    pub remaining: u32,
+    /// How long until the next request would be allowed. `None` if one is allowed right away.
+    pub retry_after: Option<Duration>
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The limit the bucket was last used with.
+    limit: RateLimit
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.refill_per_second()).min(self.limit.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.limit.burst as f64
+    }
+}
+
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    /// Number of buckets past which the next prune happens. Twice what the last prune kept, so keys that are
+    /// all in use don't make every request scan every bucket.
+    prune_at: usize
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self { by_key: HashMap::new(), prune_at: PRUNE_THRESHOLD }
+    }
+}
+
+/// Token buckets for every key seen by this process. Limits are not shared between processes.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>
+}
+
+impl RateLimiter {
+    /// Takes one token from the bucket of `key`, creating a full bucket for keys not seen before.
+    pub fn try_acquire(&self, key: &str, limit: RateLimit) -> RateLimitStatus {
+        self.try_acquire_at(key, limit, Instant::now())
+    }
+
+    fn try_acquire_at(&self, key: &str, limit: RateLimit, now: Instant) -> RateLimitStatus {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.by_key.len() > buckets.prune_at {
+            buckets.by_key.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+            buckets.prune_at = PRUNE_THRESHOLD.max(buckets.by_key.len() * 2);
+        }
+
+        let bucket = buckets.by_key.entry(key.to_string()).or_insert(Bucket { tokens: limit.burst as f64, updated: now, limit });
+        bucket.refill(now);
+        bucket.limit = limit;
+        bucket.tokens = bucket.tokens.min(limit.burst as f64);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let retry_after = if bucket.tokens >= 1.0 {
+            None
+        } else if limit.per_minute == 0 {
+            Some(Duration::MAX)
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.refill_per_second()))
+        };
+
+        RateLimitStatus { allowed, remaining: bucket.tokens as u32, retry_after }
+    }
+}
+
+impl Backend {
+    /// Used for keys without a rate limit of their own.
+    pub fn set_default_rate_limit(&mut self, limit: RateLimit) {
+        self.default_rate_limit = limit;
+    }
+
+    /// Counts a request made with the key against its rate limit and, if allowed, records it in the key's
+    /// usage counters. Fails with `InvalidApiKey` for unknown, disabled or expired keys.
+    pub async fn consume_api_key(&self, api_key: &str) -> Result<RateLimitStatus, crate::Error> {
+        let now = Utc::now();
+        let entry = self.find_api_key_entry(api_key).await?
+            .filter(|entry| entry.is_usable_at(now))
+            .ok_or(BackendError::InvalidApiKey)?;
+
+        let status = self.rate_limiter.try_acquire(&entry.value, entry.rate_limit.unwrap_or(self.default_rate_limit));
+        if status.allowed {
+            self.storage()?.record_api_key_usage(&entry.value, now).await?;
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit { burst: 3, per_minute: 60 };
+
+    fn assert_about(duration: Option<Duration>, seconds: f64) {
+        let duration = duration.expect("no retry_after").as_secs_f64();
+        assert!((duration - seconds).abs() < 1e-6, "retry_after is {}s, expected {}s", duration, seconds);
+    }
+
+    #[test]
+    fn allows_bursts_then_refills() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let status = limiter.try_acquire_at("key", LIMIT, start);
+            assert!(status.allowed);
+            assert_eq!(status.remaining, remaining);
+        }
+        let status = limiter.try_acquire_at("key", LIMIT, start);
+        assert!(!status.allowed);
+        assert_about(status.retry_after, 1.0);
+
+        let status = limiter.try_acquire_at("key", LIMIT, start + Duration::from_millis(500));
+        assert!(!status.allowed);
+        assert_about(status.retry_after, 0.5);
+        assert!(limiter.try_acquire_at("key", LIMIT, start + Duration::from_secs(1)).allowed);
+
+        // Waiting longer than a full refill takes still only gives `burst` requests.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..LIMIT.burst {
+            assert!(limiter.try_acquire_at("key", LIMIT, later).allowed);
+        }
+        assert!(!limiter.try_acquire_at("key", LIMIT, later).allowed);
+        assert!(limiter.try_acquire_at("other key", LIMIT, later).allowed);
+    }
+
+    #[test]
+    fn reports_when_the_next_request_is_allowed() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        assert_eq!(limiter.try_acquire_at("key", LIMIT, now).retry_after, None);
+        let single = RateLimit { burst: 1, per_minute: 30 };
+        assert_about(limiter.try_acquire_at("single", single, now).retry_after, 2.0);
+        let never = RateLimit { burst: 1, per_minute: 0 };
+        assert_eq!(limiter.try_acquire_at("never", never, now).retry_after, Some(Duration::MAX));
+        assert!(!limiter.try_acquire_at("never", never, now + Duration::from_secs(3600)).allowed);
+    }
+
+    #[test]
+    fn prunes_full_buckets_without_rescanning_busy_ones() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+        for key in 0..=PRUNE_THRESHOLD {
+            limiter.try_acquire_at(&key.to_string(), LIMIT, start);
+        }
+
+        // Every bucket is still missing a token, so nothing is dropped and the next prune waits for more keys.
+        limiter.try_acquire_at("busy", LIMIT, start);
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), PRUNE_THRESHOLD + 2);
+        assert_eq!(buckets.prune_at, (PRUNE_THRESHOLD + 1) * 2);
+        drop(buckets);
+
+        limiter.try_acquire_at("late", LIMIT, start + Duration::from_secs(1));
+        assert_eq!(limiter.buckets.lock().unwrap().by_key.len(), PRUNE_THRESHOLD + 3);
+
+        // Once they have all refilled, a prune keeps only the new key's bucket.
+        let limiter = RateLimiter::default();
+        for key in 0..=PRUNE_THRESHOLD {
+            limiter.try_acquire_at(&key.to_string(), LIMIT, start);
+        }
+        limiter.try_acquire_at("last", LIMIT, start + Duration::from_secs(60));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 1);
+        assert_eq!(buckets.prune_at, PRUNE_THRESHOLD);
+    }
+}
@@ -0,0 +1,312 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{ Deserialize, Serialize };
+
+use crate::{Backend, BackendError};
+use super::history::{ModerationAction, SYSTEM_MODERATOR};
+use super::moderation::{BanDuration, BanEntry};
+
+/// Something a player can be barred from without a full game ban.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RestrictionScope {
+    MapSubmission,
+    MultiplayerLobby
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Restriction {
+    #[serde(rename = "userId")]
+    pub user_id: i64,
+    pub scope: RestrictionScope,
+    #[serde(rename = "issuedAt", with = "chrono::serde::ts_milliseconds")]
+    pub issued_at: DateTime<Utc>,
+    /// `None` for restrictions that never expire.
+    #[serde(with = "chrono::serde::ts_milliseconds_option", default)]
+    pub until: Option<DateTime<Utc>>,
+    pub moderator: String,
+    pub reason: String
+}
+
+impl Restriction {
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(Utc::now())
+    }
+}
+
+/// Bans a player automatically once they collect `warnings` warnings within `window`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscalationRule {
+    pub warnings: u32,
+    pub window: Duration,
+    pub ban: BanDuration
+}
+
+/// Rules checked after every warning. When several match, the one needing the most warnings wins.
+/// Warnings issued before the player's latest ban don't count towards the next escalation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscalationPolicy {
+    pub rules: Vec<EscalationRule>
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![EscalationRule { warnings: 3, window: Duration::days(30), ban: BanDuration::For(Duration::days(1)) }]
+        }
+    }
+}
+
+impl EscalationPolicy {
+    pub fn none() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WarningOutcome {
+    /// The ban issued because the warning crossed an escalation threshold.
+    pub escalated_ban: Option<BanEntry>
+}
+
+impl Backend {
+    pub fn set_escalation_policy(&mut self, policy: EscalationPolicy) {
+        self.escalation_policy = policy;
+    }
+
+    /// Records a warning, then bans the player if the escalation policy says so.
+    pub async fn warn_player(&self, user_id: u64, moderator: &str, reason: &str) -> Result<WarningOutcome, crate::Error> {
+        self.record_moderation_action(user_id as i64, ModerationAction::Warn, moderator, reason, None, None).await?;
+
+        let escalated_ban = self.escalate_warnings(user_id).await?;
+        Ok(WarningOutcome { escalated_ban })
+    }
+
+    /// Records that the player was kicked. The kick itself is up to the game server.
+    pub async fn kick_player(&self, user_id: u64, moderator: &str, reason: &str) -> Result<(), crate::Error> {
+        self.record_moderation_action(user_id as i64, ModerationAction::Kick, moderator, reason, None, None).await
+    }
+
+    pub async fn restrict_player(&self, user_id: u64, scope: RestrictionScope, duration: BanDuration, moderator: &str, reason: &str) -> Result<Restriction, crate::Error> {
+        let now = Utc::now();
+        let restriction = Restriction {
+            user_id: user_id as i64,
+            scope,
+            issued_at: now,
+            until: duration.ends_at(now)?,
+            moderator: moderator.to_string(),
+            reason: reason.to_string()
+        };
+        self.storage()?.upsert_restriction(restriction.clone()).await?;
+
+        self.record_restriction_action(ModerationAction::Restrict, moderator, reason, restriction.clone()).await?;
+        Ok(restriction)
+    }
+
+    pub async fn lift_restriction(&self, user_id: u64, scope: RestrictionScope, moderator: &str, reason: &str) -> Result<(), crate::Error> {
+        let removed = self.storage()?.delete_restriction(user_id, scope).await?;
+        match removed {
+            Some(restriction) => self.record_restriction_action(ModerationAction::Unrestrict, moderator, reason, restriction).await,
+            None => Err(BackendError::RestrictionNotFound(user_id, scope))
+        }
+    }
+
+    /// The player's restrictions that have not expired.
+    pub async fn get_active_restrictions(&self, user_id: u64) -> Result<Vec<Restriction>, crate::Error> {
+        let now = Utc::now();
+        let restrictions = self.storage()?.restrictions_for_user(user_id).await?;
+        Ok(restrictions.into_iter().filter(|restriction| restriction.is_active_at(now)).collect())
+    }
+
+    pub async fn is_player_restricted(&self, user_id: u64, scope: RestrictionScope) -> Result<bool, crate::Error> {
+        let restrictions = self.get_active_restrictions(user_id).await?;
+        Ok(restrictions.iter().any(|restriction| restriction.scope == scope))
+    }
+
+    async fn escalate_warnings(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
+        if self.escalation_policy.rules.is_empty() {
+            return Ok(None)
+        }
+
+        let now = Utc::now();
+        let history = self.get_player_moderation_history(user_id).await?;
+        let last_ban = history.iter()
+            .filter(|record| record.action == ModerationAction::Ban)
+            .map(|record| record.time)
+            .max();
+        let warnings_since = |window: Duration| {
+            let window_start = now - window;
+            history.iter()
+                .filter(|record| record.action == ModerationAction::Warn)
+                .filter(|record| record.time >= window_start && last_ban.is_none_or(|banned| record.time > banned))
+                .count() as u32
+        };
+
+        let rule = self.escalation_policy.rules.iter()
+            .filter(|rule| warnings_since(rule.window) >= rule.warnings)
+            .max_by_key(|rule| rule.warnings);
+        let rule = match rule {
+            Some(rule) => rule,
+            None => return Ok(None)
+        };
+
+        // Don't shorten a ban that already outlasts the escalation.
+        let escalated_until = rule.ban.ends_at(now)?;
+        if let Some(active_ban) = self.get_active_ban(user_id).await? {
+            let outlasts = match (active_ban.banned_until, escalated_until) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(active_until), Some(escalated_until)) => active_until >= escalated_until
+            };
+            if outlasts {
+                return Ok(None)
+            }
+        }
+
+        let reason = format!("Automatic escalation: {} warnings within {}.", rule.warnings, describe_window(rule.window));
+        self.ban_player(user_id, rule.ban.clone(), SYSTEM_MODERATOR, &reason).await?;
+        self.find_ban_entry(user_id).await
+    }
+}
+
+/// The window in its largest whole unit, like "30 days" or "90 minutes".
+fn describe_window(window: Duration) -> String {
+    let seconds = window.num_seconds();
+    let (count, unit) = [(86_400, "day"), (3_600, "hour"), (60, "minute")]
+        .into_iter()
+        .find(|(unit_seconds, _)| seconds >= *unit_seconds && seconds % unit_seconds == 0)
+        .map(|(unit_seconds, unit)| (seconds / unit_seconds, unit))
+        .unwrap_or((seconds, "second"));
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::database::history::ModerationRecord;
+    use crate::test_support::backend;
+    use super::*;
+
+    fn record_at(backend: &Backend, action: ModerationAction, time: DateTime<Utc>) {
+        let record = ModerationRecord {
+            user_id: 42,
+            action,
+            moderator: "Moderator".to_string(),
+            reason: "Earlier".to_string(),
+            time,
+            previous: None,
+            current: None,
+            restriction: None
+        };
+        block_on(backend.storage().unwrap().append_history(record)).unwrap();
+    }
+
+    fn warn(backend: &Backend) -> Option<BanEntry> {
+        block_on(backend.warn_player(42, "Moderator", "Spam")).unwrap().escalated_ban
+    }
+
+    fn ban_until(until: Option<DateTime<Utc>>) -> BanEntry {
+        BanEntry {
+            user_id: 42,
+            banned_time: Utc::now() - Duration::days(2),
+            banned_until: until,
+            moderator: "Moderator".to_string(),
+            reason: "Exploiting".to_string()
+        }
+    }
+
+    #[test]
+    fn bans_once_the_threshold_is_reached() {
+        let backend = backend();
+        assert_eq!(warn(&backend), None);
+        assert_eq!(warn(&backend), None);
+
+        let before = Utc::now();
+        let ban = warn(&backend).unwrap();
+        assert_eq!(ban.moderator, SYSTEM_MODERATOR);
+        assert_eq!(ban.reason, "Automatic escalation: 3 warnings within 30 days.");
+        assert!(ban.banned_until.unwrap() >= before + Duration::days(1));
+        assert_eq!(block_on(backend.get_active_ban(42)).unwrap(), Some(ban));
+    }
+
+    #[test]
+    fn ignores_warnings_outside_the_window() {
+        let backend = backend();
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(31));
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(40));
+        assert_eq!(warn(&backend), None);
+
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(29));
+        assert!(warn(&backend).is_some());
+    }
+
+    #[test]
+    fn ignores_warnings_before_the_latest_ban() {
+        let backend = backend();
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(5));
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(4));
+        record_at(&backend, ModerationAction::Ban, Utc::now() - Duration::days(3));
+        record_at(&backend, ModerationAction::Unban, Utc::now() - Duration::days(2));
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::days(1));
+        assert_eq!(warn(&backend), None);
+        assert!(warn(&backend).is_some());
+    }
+
+    #[test]
+    fn picks_the_rule_needing_the_most_warnings() {
+        let policy = EscalationPolicy {
+            rules: vec![
+                EscalationRule { warnings: 2, window: Duration::hours(12), ban: BanDuration::For(Duration::hours(1)) },
+                EscalationRule { warnings: 3, window: Duration::minutes(90), ban: BanDuration::Permanent }
+            ]
+        };
+
+        // Only two of the three warnings are within 90 minutes.
+        let mut spread = backend();
+        spread.set_escalation_policy(policy.clone());
+        record_at(&spread, ModerationAction::Warn, Utc::now() - Duration::hours(2));
+        record_at(&spread, ModerationAction::Warn, Utc::now() - Duration::minutes(10));
+        let ban = warn(&spread).unwrap();
+        assert_eq!(ban.reason, "Automatic escalation: 2 warnings within 12 hours.");
+
+        let mut recent = backend();
+        recent.set_escalation_policy(policy);
+        record_at(&recent, ModerationAction::Warn, Utc::now() - Duration::minutes(20));
+        record_at(&recent, ModerationAction::Warn, Utc::now() - Duration::minutes(10));
+        let ban = warn(&recent).unwrap();
+        assert_eq!(ban.banned_until, None);
+        assert_eq!(ban.reason, "Automatic escalation: 3 warnings within 90 minutes.");
+    }
+
+    #[test]
+    fn does_not_shorten_a_longer_ban() {
+        for longer in [ban_until(None), ban_until(Some(Utc::now() + Duration::days(7)))] {
+            let backend = backend();
+            block_on(backend.storage().unwrap().upsert_ban(longer.clone())).unwrap();
+            record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::hours(2));
+            record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::hours(1));
+
+            assert_eq!(warn(&backend), None);
+            assert_eq!(block_on(backend.get_active_ban(42)).unwrap(), Some(longer));
+        }
+
+        let backend = backend();
+        block_on(backend.storage().unwrap().upsert_ban(ban_until(Some(Utc::now() + Duration::hours(1))))).unwrap();
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::hours(2));
+        record_at(&backend, ModerationAction::Warn, Utc::now() - Duration::hours(1));
+        let ban = warn(&backend).unwrap();
+        assert!(ban.banned_until.unwrap() > Utc::now() + Duration::hours(23));
+    }
+
+    #[test]
+    fn describes_windows_in_their_largest_whole_unit() {
+        assert_eq!(describe_window(Duration::days(30)), "30 days");
+        assert_eq!(describe_window(Duration::days(1)), "1 day");
+        assert_eq!(describe_window(Duration::hours(36)), "36 hours");
+        assert_eq!(describe_window(Duration::minutes(90)), "90 minutes");
+        assert_eq!(describe_window(Duration::seconds(45)), "45 seconds");
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::database::api_keys::{usage_day, ApiKey};
+use crate::database::appeals::{AppealStatus, BanAppeal};
+use crate::database::ban_query::{BanCursor, BanPage, BanQuery, SortOrder};
+use crate::database::history::ModerationRecord;
+use crate::database::links::AccountLink;
+use crate::database::moderation::BanEntry;
+use crate::database::restrictions::{Restriction, RestrictionScope};
+use super::{ApiKeyChanges, ApiKeyStore, ApiKeyUpdate, AppealStore, BanStore, HistoryStore, LinkStore, RestrictionStore};
+
+/// Keeps everything in process memory. Useful for tests and local development, nothing is persisted.
+#[derive(Default)]
+pub struct MemoryStore {
+    bans: RwLock<HashMap<i64, BanEntry>>,
+    history: RwLock<Vec<ModerationRecord>>,
+    restrictions: RwLock<HashMap<(i64, RestrictionScope), Restriction>>,
+    appeals: RwLock<Vec<BanAppeal>>,
+    links: RwLock<HashMap<(i64, i64), AccountLink>>,
+    api_keys: RwLock<Vec<ApiKey>>
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BanStore for MemoryStore {
+    async fn find_ban(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
+        Ok(self.bans.read().unwrap().get(&(user_id as i64)).cloned())
+    }
+
+    async fn find_bans(&self, user_ids: &[u64]) -> Result<Vec<BanEntry>, crate::Error> {
+        let bans = self.bans.read().unwrap();
+        Ok(user_ids.iter().filter_map(|user_id| bans.get(&(*user_id as i64)).cloned()).collect())
+    }
+
+    async fn list_bans(&self) -> Result<BanPage, crate::Error> {
+        let entries = self.bans.read().unwrap().values().cloned().collect();
+        Ok(BanPage { entries, invalid_documents: Vec::new(), next_cursor: None })
+    }
+
+    async fn query_bans(&self, query: &BanQuery, now: DateTime<Utc>) -> Result<BanPage, crate::Error> {
+        let cursor = query.decoded_cursor()?;
+        let sort_key = |entry: &BanEntry| BanCursor::for_entry(entry, query.sort_by);
+
+        let mut entries: Vec<BanEntry> = self.bans.read().unwrap()
+            .values()
+            .filter(|entry| query.matches(entry, now))
+            .filter(|entry| cursor.is_none_or(|cursor| cursor.is_before(&sort_key(entry), query.order)))
+            .cloned()
+            .collect();
+        entries.sort_by_key(|entry| {
+            let key = sort_key(entry);
+            (key.sort_value, key.user_id)
+        });
+        if query.order == SortOrder::Descending {
+            entries.reverse();
+        }
+
+        let page_size = query.page_size() as usize;
+        let has_more = entries.len() > page_size;
+        entries.truncate(page_size);
+        let next_cursor = entries.last().filter(|_| has_more).map(|entry| sort_key(entry).encode());
+
+        Ok(BanPage { entries, invalid_documents: Vec::new(), next_cursor })
+    }
+
+    async fn upsert_ban(&self, entry: BanEntry) -> Result<(), crate::Error> {
+        self.bans.write().unwrap().insert(entry.user_id, entry);
+        Ok(())
+    }
+
+    async fn upsert_bans(&self, entries: Vec<BanEntry>) -> Result<Vec<(i64, String)>, crate::Error> {
+        let mut bans = self.bans.write().unwrap();
+        for entry in entries {
+            bans.insert(entry.user_id, entry);
+        }
+        Ok(Vec::new())
+    }
+
+    async fn delete_ban(&self, user_id: u64) -> Result<bool, crate::Error> {
+        Ok(self.bans.write().unwrap().remove(&(user_id as i64)).is_some())
+    }
+
+    async fn delete_bans(&self, user_ids: &[u64]) -> Result<u64, crate::Error> {
+        let mut bans = self.bans.write().unwrap();
+        Ok(user_ids.iter().filter(|user_id| bans.remove(&(**user_id as i64)).is_some()).count() as u64)
+    }
+
+    async fn delete_expired_bans(&self, now: DateTime<Utc>) -> Result<Vec<BanEntry>, crate::Error> {
+        let mut bans = self.bans.write().unwrap();
+        let mut expired = Vec::new();
+        bans.retain(|_, entry| {
+            if entry.is_active_at(now) {
+                return true
+            }
+            expired.push(entry.clone());
+            false
+        });
+        Ok(expired)
+    }
+
+    async fn migrate_legacy_bans(&self) -> Result<u64, crate::Error> {
+        // Entries only ever live in memory in the current format.
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl HistoryStore for MemoryStore {
+    async fn append_history(&self, record: ModerationRecord) -> Result<(), crate::Error> {
+        self.history.write().unwrap().push(record);
+        Ok(())
+    }
+
+    async fn append_history_batch(&self, records: Vec<ModerationRecord>) -> Result<(), crate::Error> {
+        self.history.write().unwrap().extend(records);
+        Ok(())
+    }
+
+    async fn history_for_user(&self, user_id: u64) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let mut records: Vec<ModerationRecord> = self.history.read().unwrap()
+            .iter()
+            .filter(|record| record.user_id == user_id as i64)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| record.time);
+        Ok(records)
+    }
+
+    async fn list_history(&self) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let mut records = self.history.read().unwrap().clone();
+        records.sort_by_key(|record| record.time);
+        Ok(records)
+    }
+
+    async fn history_for_moderator(&self, moderator: &str, limit: u32) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let mut records: Vec<ModerationRecord> = self.history.read().unwrap()
+            .iter()
+            .filter(|record| record.moderator == moderator)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.time));
+        records.truncate(limit as usize);
+        Ok(records)
+    }
+}
+
+#[async_trait]
+impl RestrictionStore for MemoryStore {
+    async fn upsert_restriction(&self, restriction: Restriction) -> Result<(), crate::Error> {
+        self.restrictions.write().unwrap().insert((restriction.user_id, restriction.scope), restriction);
+        Ok(())
+    }
+
+    async fn restrictions_for_user(&self, user_id: u64) -> Result<Vec<Restriction>, crate::Error> {
+        Ok(self.restrictions.read().unwrap()
+            .values()
+            .filter(|restriction| restriction.user_id == user_id as i64)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_restriction(&self, user_id: u64, scope: RestrictionScope) -> Result<Option<Restriction>, crate::Error> {
+        Ok(self.restrictions.write().unwrap().remove(&(user_id as i64, scope)))
+    }
+}
+
+#[async_trait]
+impl AppealStore for MemoryStore {
+    async fn insert_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error> {
+        let mut appeals = self.appeals.write().unwrap();
+        let is_duplicate = |stored: &BanAppeal| {
+            stored.status == AppealStatus::Pending && stored.user_id == appeal.user_id && stored.ban_time == appeal.ban_time
+        };
+        if appeal.status == AppealStatus::Pending && appeals.iter().any(is_duplicate) {
+            return Ok(false)
+        }
+        appeals.push(appeal);
+        Ok(true)
+    }
+
+    async fn find_appeal(&self, appeal_id: &str) -> Result<Option<BanAppeal>, crate::Error> {
+        Ok(self.appeals.read().unwrap().iter().find(|appeal| appeal.id == appeal_id).cloned())
+    }
+
+    async fn appeals_for_user(&self, user_id: u64) -> Result<Vec<BanAppeal>, crate::Error> {
+        Ok(self.appeals.read().unwrap()
+            .iter()
+            .filter(|appeal| appeal.user_id == user_id as i64)
+            .cloned()
+            .collect())
+    }
+
+    async fn pending_appeals(&self, limit: u32) -> Result<Vec<BanAppeal>, crate::Error> {
+        Ok(self.appeals.read().unwrap()
+            .iter()
+            .filter(|appeal| appeal.status == AppealStatus::Pending)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error> {
+        let mut appeals = self.appeals.write().unwrap();
+        match appeals.iter_mut().find(|stored| stored.id == appeal.id && stored.status == AppealStatus::Pending) {
+            Some(stored) => {
+                *stored = appeal;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+}
+
+#[async_trait]
+impl LinkStore for MemoryStore {
+    async fn upsert_link(&self, link: AccountLink) -> Result<(), crate::Error> {
+        self.links.write().unwrap().insert((link.user_id, link.linked_user_id), link);
+        Ok(())
+    }
+
+    async fn links_for_user(&self, user_id: u64) -> Result<Vec<AccountLink>, crate::Error> {
+        let user_id = user_id as i64;
+        Ok(self.links.read().unwrap()
+            .values()
+            .filter(|link| link.user_id == user_id || link.linked_user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_link(&self, user_id: u64, linked_user_id: u64) -> Result<bool, crate::Error> {
+        Ok(self.links.write().unwrap().remove(&(user_id as i64, linked_user_id as i64)).is_some())
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MemoryStore {
+    async fn find_api_key(&self, value: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap().iter().find(|key| key.value == value).cloned())
+    }
+
+    async fn find_api_key_by_owner(&self, roblox_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap().iter().find(|key| key.assign_owner == roblox_id).cloned())
+    }
+
+    async fn api_keys_for_owner(&self, roblox_id: &str) -> Result<Vec<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap()
+            .iter()
+            .filter(|key| key.assign_owner == roblox_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_api_key_by_discord_user(&self, discord_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap()
+            .iter()
+            .find(|key| key.associated_discord_user.as_deref() == Some(discord_id))
+            .cloned())
+    }
+
+    async fn api_keys_for_discord_user(&self, discord_id: &str) -> Result<Vec<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap()
+            .iter()
+            .filter(|key| key.associated_discord_user.as_deref() == Some(discord_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_api_key(&self, key: ApiKey) -> Result<bool, crate::Error> {
+        let mut api_keys = self.api_keys.write().unwrap();
+        if api_keys.iter().any(|existing| existing.value == key.value) {
+            return Ok(false)
+        }
+        api_keys.push(key);
+        Ok(true)
+    }
+
+    async fn find_api_key_by_id(&self, key_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap().iter().find(|key| key.key_id == key_id).cloned())
+    }
+
+    async fn api_keys_needing_migration(&self) -> Result<Vec<ApiKey>, crate::Error> {
+        Ok(self.api_keys.read().unwrap()
+            .iter()
+            .filter(|key| !key.hashed || key.key_id.is_empty())
+            .cloned()
+            .collect())
+    }
+
+    async fn update_api_key(&self, current: &ApiKey, changes: ApiKeyChanges) -> Result<ApiKeyUpdate, crate::Error> {
+        let mut api_keys = self.api_keys.write().unwrap();
+        let is_current = |existing: &ApiKey| {
+            existing.key_id == current.key_id && existing.value == current.value && existing.version == current.version
+        };
+        let index = match api_keys.iter().position(is_current) {
+            Some(index) => index,
+            None => return Ok(ApiKeyUpdate::Stale)
+        };
+        if let Some(value) = &changes.value {
+            if api_keys.iter().enumerate().any(|(other, existing)| other != index && &existing.value == value) {
+                return Ok(ApiKeyUpdate::DuplicateValue)
+            }
+        }
+        let key = &mut api_keys[index];
+        changes.apply(key);
+        key.version += 1;
+        Ok(ApiKeyUpdate::Updated)
+    }
+
+    async fn record_api_key_usage(&self, value: &str, time: DateTime<Utc>) -> Result<(), crate::Error> {
+        if let Some(key) = self.api_keys.write().unwrap().iter_mut().find(|key| key.value == value) {
+            key.last_used = Some(time);
+            key.total_requests += 1;
+            *key.daily_requests.entry(usage_day(time.date_naive())).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, key_id: &str) -> Result<bool, crate::Error> {
+        let mut api_keys = self.api_keys.write().unwrap();
+        let count = api_keys.len();
+        api_keys.retain(|key| key.key_id != key_id);
+        Ok(api_keys.len() < count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn api_key(key_id: &str, value: &str) -> ApiKey {
+        ApiKey {
+            key_id: key_id.to_string(),
+            value: value.to_string(),
+            assign_owner: "None".to_string(),
+            associated_discord_user: None,
+            enabled: true,
+            time_created: 0.0,
+            hashed: true,
+            scopes: Vec::new(),
+            expires_at: None,
+            rate_limit: None,
+            last_used: None,
+            total_requests: 0,
+            daily_requests: BTreeMap::new(),
+            version: 0
+        }
+    }
+
+    fn rotation(value: &str) -> ApiKeyChanges {
+        ApiKeyChanges { value: Some(value.to_string()), ..ApiKeyChanges::default() }
+    }
+
+    fn revocation() -> ApiKeyChanges {
+        ApiKeyChanges { enabled: Some(false), ..ApiKeyChanges::default() }
+    }
+
+    #[test]
+    fn updating_a_changed_api_key_is_stale() {
+        let store = MemoryStore::new();
+        let original = api_key("id", "old");
+        assert!(block_on(store.insert_api_key(original.clone())).unwrap());
+
+        // The value stays the same, only the version tells the rotation its read is outdated.
+        assert_eq!(block_on(store.update_api_key(&original, revocation())).unwrap(), ApiKeyUpdate::Updated);
+        assert_eq!(block_on(store.update_api_key(&original, rotation("new"))).unwrap(), ApiKeyUpdate::Stale);
+
+        let stored = block_on(store.find_api_key_by_id("id")).unwrap().unwrap();
+        assert_eq!(stored.value, "old");
+        assert!(!stored.enabled);
+        assert_eq!(stored.version, 1);
+    }
+
+    #[test]
+    fn updates_keep_usage_counted_after_the_read() {
+        let store = MemoryStore::new();
+        let original = api_key("id", "value");
+        block_on(store.insert_api_key(original.clone())).unwrap();
+
+        let time = Utc::now();
+        block_on(store.record_api_key_usage("value", time)).unwrap();
+        assert_eq!(block_on(store.update_api_key(&original, revocation())).unwrap(), ApiKeyUpdate::Updated);
+
+        let stored = block_on(store.find_api_key_by_id("id")).unwrap().unwrap();
+        assert!(!stored.enabled);
+        assert_eq!(stored.total_requests, 1);
+        assert_eq!(stored.requests_on(time.date_naive()), 1);
+        assert_eq!(stored.last_used, Some(time));
+    }
+
+    #[test]
+    fn updating_to_a_taken_value_is_refused() {
+        let store = MemoryStore::new();
+        let first = api_key("first", "a");
+        block_on(store.insert_api_key(first.clone())).unwrap();
+        block_on(store.insert_api_key(api_key("second", "b"))).unwrap();
+
+        assert_eq!(block_on(store.update_api_key(&first, rotation("b"))).unwrap(), ApiKeyUpdate::DuplicateValue);
+    }
+
+    fn pending_appeal(id: &str) -> BanAppeal {
+        BanAppeal {
+            id: id.to_string(),
+            user_id: 42,
+            ban_time: Utc::now(),
+            message: "Sorry".to_string(),
+            status: AppealStatus::Pending,
+            submitted_at: Utc::now(),
+            reviewed_by: None,
+            reviewed_at: None,
+            moderator_note: None
+        }
+    }
+
+    #[test]
+    fn allows_one_pending_appeal_per_ban() {
+        let store = MemoryStore::new();
+        let appeal = pending_appeal("first");
+        assert!(block_on(store.insert_appeal(appeal.clone())).unwrap());
+
+        let second = BanAppeal { id: "second".to_string(), ..appeal.clone() };
+        assert!(!block_on(store.insert_appeal(second)).unwrap());
+
+        let rejected = BanAppeal { id: "rejected".to_string(), status: AppealStatus::Rejected, ..appeal };
+        assert!(block_on(store.insert_appeal(rejected)).unwrap());
+    }
+
+    #[test]
+    fn updates_only_pending_appeals() {
+        let store = MemoryStore::new();
+        let appeal = pending_appeal("first");
+        block_on(store.insert_appeal(appeal.clone())).unwrap();
+
+        let rejected = BanAppeal { status: AppealStatus::Rejected, reviewed_by: Some("Reviewer".to_string()), ..appeal.clone() };
+        assert!(block_on(store.update_appeal(rejected.clone())).unwrap());
+        let accepted = BanAppeal { status: AppealStatus::Accepted, ..appeal };
+        assert!(!block_on(store.update_appeal(accepted)).unwrap());
+        assert!(!block_on(store.update_appeal(pending_appeal("unknown"))).unwrap());
+
+        assert_eq!(block_on(store.find_appeal("first")).unwrap(), Some(rejected));
+    }
+}
@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::api_keys::{ApiKey, ApiScope};
+use super::appeals::BanAppeal;
+use super::ban_query::{BanPage, BanQuery};
+use super::history::ModerationRecord;
+use super::links::AccountLink;
+use super::moderation::BanEntry;
+use super::rate_limit::RateLimit;
+use super::restrictions::{Restriction, RestrictionScope};
+
+mod memory;
+mod mongo;
+
+pub use memory::MemoryStore;
+pub use mongo::{CollectionNames, MongoStore};
+
+/// Fields of a stored API key to change. `None` leaves the stored field as it is. Usage counters cannot be
+/// changed this way, so requests counted meanwhile are never overwritten.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApiKeyChanges {
+    pub key_id: Option<String>,
+    pub value: Option<String>,
+    pub hashed: Option<bool>,
+    pub enabled: Option<bool>,
+    pub assign_owner: Option<String>,
+    pub associated_discord_user: Option<Option<String>>,
+    pub scopes: Option<Vec<ApiScope>>,
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub rate_limit: Option<Option<RateLimit>>
+}
+
+impl ApiKeyChanges {
+    pub fn apply(&self, key: &mut ApiKey) {
+        if let Some(key_id) = &self.key_id {
+            key.key_id = key_id.clone();
+        }
+        if let Some(value) = &self.value {
+            key.value = value.clone();
+        }
+        if let Some(hashed) = self.hashed {
+            key.hashed = hashed;
+        }
+        if let Some(enabled) = self.enabled {
+            key.enabled = enabled;
+        }
+        if let Some(assign_owner) = &self.assign_owner {
+            key.assign_owner = assign_owner.clone();
+        }
+        if let Some(associated_discord_user) = &self.associated_discord_user {
+            key.associated_discord_user = associated_discord_user.clone();
+        }
+        if let Some(scopes) = &self.scopes {
+            key.scopes = scopes.clone();
+        }
+        if let Some(expires_at) = self.expires_at {
+            key.expires_at = expires_at;
+        }
+        if let Some(rate_limit) = self.rate_limit {
+            key.rate_limit = rate_limit;
+        }
+    }
+}
+
+/// What `ApiKeyStore::update_api_key` did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiKeyUpdate {
+    Updated,
+    /// The key was changed or removed since it was read, nothing was changed.
+    Stale,
+    /// Another key already has the new value, nothing was changed.
+    DuplicateValue
+}
+
+#[async_trait]
+pub trait BanStore: Send + Sync {
+    async fn find_ban(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error>;
+    /// The entries of the listed users that have one.
+    async fn find_bans(&self, user_ids: &[u64]) -> Result<Vec<BanEntry>, crate::Error>;
+    /// Every ban in a single page. Entries that cannot be read go into `invalid_documents`.
+    async fn list_bans(&self) -> Result<BanPage, crate::Error>;
+    /// One page of bans matching `query`, `now` deciding which bans count as active.
+    async fn query_bans(&self, query: &BanQuery, now: DateTime<Utc>) -> Result<BanPage, crate::Error>;
+    /// Inserts the entry, or replaces the existing entry for the same user.
+    async fn upsert_ban(&self, entry: BanEntry) -> Result<(), crate::Error>;
+    /// `upsert_ban` for many entries in one batch. Returns the users whose entry could not be written, with
+    /// the reason.
+    async fn upsert_bans(&self, entries: Vec<BanEntry>) -> Result<Vec<(i64, String)>, crate::Error>;
+    /// Returns whether an entry was removed.
+    async fn delete_ban(&self, user_id: u64) -> Result<bool, crate::Error>;
+    /// Returns how many entries were removed.
+    async fn delete_bans(&self, user_ids: &[u64]) -> Result<u64, crate::Error>;
+    /// Removes every non-permanent ban that ended at or before `now`. Returns the removed entries.
+    async fn delete_expired_bans(&self, now: DateTime<Utc>) -> Result<Vec<BanEntry>, crate::Error>;
+    /// Rewrites entries stored without a schema version. Returns how many were rewritten.
+    async fn migrate_legacy_bans(&self) -> Result<u64, crate::Error>;
+}
+
+#[async_trait]
+pub trait HistoryStore: Send + Sync {
+    async fn append_history(&self, record: ModerationRecord) -> Result<(), crate::Error>;
+    async fn append_history_batch(&self, records: Vec<ModerationRecord>) -> Result<(), crate::Error>;
+    /// Oldest first.
+    async fn history_for_user(&self, user_id: u64) -> Result<Vec<ModerationRecord>, crate::Error>;
+    /// Every record, oldest first.
+    async fn list_history(&self) -> Result<Vec<ModerationRecord>, crate::Error>;
+    /// Newest first, at most `limit` records.
+    async fn history_for_moderator(&self, moderator: &str, limit: u32) -> Result<Vec<ModerationRecord>, crate::Error>;
+}
+
+#[async_trait]
+pub trait RestrictionStore: Send + Sync {
+    /// Inserts the restriction, or replaces the player's existing restriction with the same scope.
+    async fn upsert_restriction(&self, restriction: Restriction) -> Result<(), crate::Error>;
+    /// Includes expired restrictions.
+    async fn restrictions_for_user(&self, user_id: u64) -> Result<Vec<Restriction>, crate::Error>;
+    /// Returns the removed restriction, if there was one.
+    async fn delete_restriction(&self, user_id: u64, scope: RestrictionScope) -> Result<Option<Restriction>, crate::Error>;
+}
+
+#[async_trait]
+pub trait AppealStore: Send + Sync {
+    /// Returns false without inserting if the appeal is pending and another pending appeal for the same ban
+    /// exists.
+    async fn insert_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error>;
+    async fn find_appeal(&self, appeal_id: &str) -> Result<Option<BanAppeal>, crate::Error>;
+    /// Oldest first.
+    async fn appeals_for_user(&self, user_id: u64) -> Result<Vec<BanAppeal>, crate::Error>;
+    /// Oldest first, at most `limit` appeals.
+    async fn pending_appeals(&self, limit: u32) -> Result<Vec<BanAppeal>, crate::Error>;
+    /// Replaces the stored appeal with the same ID, as long as it is still pending. Returns whether it was.
+    async fn update_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error>;
+}
+
+#[async_trait]
+pub trait LinkStore: Send + Sync {
+    /// Inserts the link, or replaces the existing link between the same two accounts.
+    async fn upsert_link(&self, link: AccountLink) -> Result<(), crate::Error>;
+    /// Links with the user on either side.
+    async fn links_for_user(&self, user_id: u64) -> Result<Vec<AccountLink>, crate::Error>;
+    /// Returns whether a link was removed.
+    async fn delete_link(&self, user_id: u64, linked_user_id: u64) -> Result<bool, crate::Error>;
+}
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn find_api_key(&self, value: &str) -> Result<Option<ApiKey>, crate::Error>;
+    async fn find_api_key_by_owner(&self, roblox_id: &str) -> Result<Option<ApiKey>, crate::Error>;
+    async fn api_keys_for_owner(&self, roblox_id: &str) -> Result<Vec<ApiKey>, crate::Error>;
+    async fn find_api_key_by_discord_user(&self, discord_id: &str) -> Result<Option<ApiKey>, crate::Error>;
+    async fn api_keys_for_discord_user(&self, discord_id: &str) -> Result<Vec<ApiKey>, crate::Error>;
+    /// Returns false without inserting if a key with the same value exists.
+    async fn insert_api_key(&self, key: ApiKey) -> Result<bool, crate::Error>;
+    async fn find_api_key_by_id(&self, key_id: &str) -> Result<Option<ApiKey>, crate::Error>;
+    /// Keys stored in plain text or without a key ID.
+    async fn api_keys_needing_migration(&self) -> Result<Vec<ApiKey>, crate::Error>;
+    /// Applies `changes` and increments the version, as long as the stored key still has the key ID, value and
+    /// version of `current`.
+    async fn update_api_key(&self, current: &ApiKey, changes: ApiKeyChanges) -> Result<ApiKeyUpdate, crate::Error>;
+    /// Counts one request in the usage counters of the key with value `value`.
+    async fn record_api_key_usage(&self, value: &str, time: DateTime<Utc>) -> Result<(), crate::Error>;
+    /// Returns whether a key was removed.
+    async fn delete_api_key(&self, key_id: &str) -> Result<bool, crate::Error>;
+}
+
+/// Everything `Backend` needs to persist. Implemented automatically for any type implementing all the stores.
+pub trait Storage: BanStore + HistoryStore + RestrictionStore + AppealStore + LinkStore + ApiKeyStore {}
+
+impl<T: BanStore + HistoryStore + RestrictionStore + AppealStore + LinkStore + ApiKeyStore> Storage for T {}
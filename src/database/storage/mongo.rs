@@ -0,0 +1,794 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{self, doc, Bson, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{FindOptions, IndexOptions, ReplaceOptions},
+    Collection,
+    Database,
+    IndexModel
+};
+use serde::{Deserialize, Serialize};
+
+use crate::BackendError;
+use crate::database::api_keys::{usage_day, ApiKey};
+use crate::database::appeals::{AppealStatus, BanAppeal};
+use crate::database::ban_query::{BanCursor, BanPage, BanQuery, BanSortField, BanStatus, InvalidBanDocument, SortOrder};
+use crate::database::history::ModerationRecord;
+use crate::database::links::AccountLink;
+use crate::database::moderation::BanEntry;
+use crate::database::restrictions::{Restriction, RestrictionScope};
+use super::{ApiKeyChanges, ApiKeyStore, ApiKeyUpdate, AppealStore, BanStore, HistoryStore, LinkStore, RestrictionStore};
+
+const BANS_COLLECTION: &str = "bannedplayers";
+const API_KEYS_COLLECTION: &str = "apikeys";
+const HISTORY_COLLECTION: &str = "moderationhistory";
+const RESTRICTIONS_COLLECTION: &str = "restrictions";
+const APPEALS_COLLECTION: &str = "banappeals";
+const LINKS_COLLECTION: &str = "accountlinks";
+const DUPLICATE_KEY_CODE: i32 = 11000;
+/// Statements per update command of `upsert_bans`, well below the server's limit of 100,000.
+const UPSERT_BATCH_SIZE: usize = 1000;
+
+/// Names of the MongoDB collections `MongoStore` reads and writes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CollectionNames {
+    pub bans: String,
+    pub history: String,
+    pub restrictions: String,
+    pub appeals: String,
+    pub links: String,
+    pub api_keys: String
+}
+
+impl Default for CollectionNames {
+    fn default() -> Self {
+        Self {
+            bans: BANS_COLLECTION.to_string(),
+            history: HISTORY_COLLECTION.to_string(),
+            restrictions: RESTRICTIONS_COLLECTION.to_string(),
+            appeals: APPEALS_COLLECTION.to_string(),
+            links: LINKS_COLLECTION.to_string(),
+            api_keys: API_KEYS_COLLECTION.to_string()
+        }
+    }
+}
+
+pub struct MongoStore {
+    database: Database,
+    collections: CollectionNames
+}
+
+impl MongoStore {
+    /// Creates the indexes the store relies on before returning it, see `with_collections`.
+    pub async fn new(database: Database) -> Result<Self, crate::Error> {
+        Self::with_collections(database, CollectionNames::default()).await
+    }
+
+    /// Creates the indexes the store relies on before returning it. Fails with `InvalidConfig` if a player has
+    /// several bans, existing API keys share a value or a ban has several pending appeals, since the unique
+    /// indexes cannot be built then.
+    pub async fn with_collections(database: Database, collections: CollectionNames) -> Result<Self, crate::Error> {
+        let store = Self { database, collections };
+        store.ensure_indexes().await?;
+        Ok(store)
+    }
+
+    /// The unique index on banned user IDs, the unique index on API key values, the index on key IDs, and the
+    /// unique index allowing one pending appeal per ban. Does nothing for indexes that already exist.
+    async fn ensure_indexes(&self) -> Result<(), crate::Error> {
+        let unique = IndexOptions::builder().unique(true).build();
+        let user_index = IndexModel::builder().keys(doc! { "userId": 1 }).options(unique.clone()).build();
+        match self.bans().create_index(user_index, None).await {
+            Ok(_) => {},
+            Err(err) if is_duplicate_key(&err) => return Err(BackendError::InvalidConfig(format!(
+                "Some players have more than one ban in collection {}, remove the extra bans so the unique index on user IDs can be created.",
+                self.collections.bans
+            ))),
+            Err(err) => return Err(err.into())
+        }
+
+        let value_index = IndexModel::builder().keys(doc! { "value": 1 }).options(unique).build();
+        let key_id_index = IndexModel::builder().keys(doc! { "keyId": 1 }).build();
+        match self.api_keys().create_indexes([value_index, key_id_index], None).await {
+            Ok(_) => {},
+            Err(err) if is_duplicate_key(&err) => return Err(self.duplicate_api_keys_error().await?),
+            Err(err) => return Err(err.into())
+        }
+
+        let pending = IndexOptions::builder()
+            .unique(true)
+            .partial_filter_expression(doc! { "status": bson::to_bson(&AppealStatus::Pending)? })
+            .build();
+        let pending_index = IndexModel::builder().keys(doc! { "userId": 1, "banTime": 1 }).options(pending).build();
+        match self.appeals().create_index(pending_index, None).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_duplicate_key(&err) => Err(BackendError::InvalidConfig(format!(
+                "Some bans have more than one pending appeal in collection {}, review the extra appeals so the unique index on pending appeals can be created.",
+                self.collections.appeals
+            ))),
+            Err(err) => Err(err.into())
+        }
+    }
+
+    /// Keys generated before keys were random could collide, and such keys have to be cleaned up by hand.
+    async fn duplicate_api_keys_error(&self) -> Result<BackendError, crate::Error> {
+        let pipeline = [
+            doc! { "$group": { "_id": "$value", "count": { "$sum": 1 } } },
+            doc! { "$match": { "count": { "$gt": 1 } } },
+            doc! { "$count": "values" }
+        ];
+        let counts: Vec<Document> = self.database.collection::<Document>(&self.collections.api_keys)
+            .aggregate(pipeline, None)
+            .await?
+            .try_collect()
+            .await?;
+        let duplicates = counts.first().and_then(|count| lenient_i64(count, "values")).unwrap_or(0);
+
+        Ok(BackendError::InvalidConfig(format!(
+            "{} API key values are stored more than once in collection {}, delete the duplicate keys so the unique index on values can be created.",
+            duplicates,
+            self.collections.api_keys
+        )))
+    }
+
+    fn bans(&self) -> Collection<BanEntry> {
+        self.database.collection(&self.collections.bans)
+    }
+
+    fn raw_bans(&self) -> Collection<Document> {
+        self.database.collection(&self.collections.bans)
+    }
+
+    fn history(&self) -> Collection<ModerationRecord> {
+        self.database.collection(&self.collections.history)
+    }
+
+    fn restrictions(&self) -> Collection<Restriction> {
+        self.database.collection(&self.collections.restrictions)
+    }
+
+    fn appeals(&self) -> Collection<BanAppeal> {
+        self.database.collection(&self.collections.appeals)
+    }
+
+    fn links(&self) -> Collection<AccountLink> {
+        self.database.collection(&self.collections.links)
+    }
+
+    fn api_keys(&self) -> Collection<ApiKey> {
+        self.database.collection(&self.collections.api_keys)
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Also true for index builds failing on existing duplicates, which are reported as command errors.
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => write_error.code == DUPLICATE_KEY_CODE,
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_CODE,
+        _ => false
+    }
+}
+
+fn bson_to_i64(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(value) => Some(*value as i64),
+        Bson::Int64(value) => Some(*value),
+        Bson::Double(value) => Some(*value as i64),
+        _ => None
+    }
+}
+
+fn lenient_i64(document: &Document, key: &str) -> Option<i64> {
+    bson_to_i64(document.get(key)?)
+}
+
+fn sort_field_name(sort_by: BanSortField) -> &'static str {
+    match sort_by {
+        BanSortField::BannedTime => "bannedTime",
+        BanSortField::UserId => "userId"
+    }
+}
+
+/// `$set` fields of the changes, named as `ApiKey` serializes them.
+fn api_key_changes_document(changes: ApiKeyChanges) -> Result<Document, crate::Error> {
+    let mut fields = Document::new();
+    if let Some(key_id) = changes.key_id {
+        fields.insert("keyId", key_id);
+    }
+    if let Some(value) = changes.value {
+        fields.insert("value", value);
+    }
+    if let Some(hashed) = changes.hashed {
+        fields.insert("hashed", hashed);
+    }
+    if let Some(enabled) = changes.enabled {
+        fields.insert("enabled", enabled);
+    }
+    if let Some(assign_owner) = changes.assign_owner {
+        fields.insert("assignOwner", assign_owner);
+    }
+    if let Some(associated_discord_user) = changes.associated_discord_user {
+        fields.insert("associatedDiscordUser", associated_discord_user);
+    }
+    if let Some(scopes) = changes.scopes {
+        fields.insert("scopes", bson::to_bson(&scopes)?);
+    }
+    if let Some(expires_at) = changes.expires_at {
+        fields.insert("expiresAt", expires_at.map(|expires_at| expires_at.timestamp_millis()));
+    }
+    if let Some(rate_limit) = changes.rate_limit {
+        fields.insert("rateLimit", bson::to_bson(&rate_limit)?);
+    }
+    Ok(fields)
+}
+
+/// Adds the ban to the page's entries, or to its invalid documents if it cannot be read.
+fn read_ban_document(document: Document, page: &mut BanPage) {
+    let id = document.get_object_id("_id").ok().map(|id| id.to_hex());
+    match bson::from_document::<BanEntry>(document) {
+        Ok(entry) => page.entries.push(entry),
+        Err(err) => page.invalid_documents.push(InvalidBanDocument { id, error: err.to_string() })
+    }
+}
+
+/// Bans `BanEntry` reads as permanent: `bannedUntil` of -1, or legacy entries whose duration wrapped below zero.
+fn permanent_ban_filter() -> Document {
+    doc! { "$or": [
+        { "bannedUntil": -1 },
+        { "schemaVersion": { "$exists": false }, "$expr": { "$lt": ["$bannedUntil", "$bannedTime"] } }
+    ] }
+}
+
+fn temporary_ban_filter() -> Document {
+    doc! { "$nor": [permanent_ban_filter()] }
+}
+
+/// `bannedUntil` of non-permanent bans, with legacy entries corrected the way `BanEntry` reads them.
+fn effective_banned_until() -> Bson {
+    let legacy_minutes = doc! { "$trunc": { "$divide": [{ "$subtract": ["$bannedUntil", "$bannedTime"] }, 60] } };
+    Bson::Document(doc! { "$cond": [
+        { "$eq": [{ "$type": "$schemaVersion" }, "missing"] },
+        { "$add": ["$bannedTime", { "$multiply": [legacy_minutes, 60_000] }] },
+        "$bannedUntil"
+    ] })
+}
+
+/// Filter matching `BanQuery::matches` plus the cursor position.
+fn ban_query_filter(query: &BanQuery, cursor: Option<BanCursor>, now: DateTime<Utc>) -> Document {
+    let now = now.timestamp_millis();
+    let mut conditions: Vec<Document> = Vec::new();
+
+    if let Some(moderator) = &query.moderator {
+        conditions.push(doc! { "moderator": moderator });
+    }
+    match query.status {
+        BanStatus::Any => {},
+        BanStatus::Active => conditions.push(doc! { "$or": [
+            permanent_ban_filter(),
+            { "$expr": { "$gt": [effective_banned_until(), now] } }
+        ] }),
+        BanStatus::Expired => conditions.push(doc! { "$and": [
+            temporary_ban_filter(),
+            { "$expr": { "$lte": [effective_banned_until(), now] } }
+        ] })
+    }
+    match query.permanent {
+        Some(true) => conditions.push(permanent_ban_filter()),
+        Some(false) => conditions.push(temporary_ban_filter()),
+        None => {}
+    }
+    if let Some(after) = query.banned_after {
+        conditions.push(doc! { "bannedTime": { "$gte": after.timestamp_millis() } });
+    }
+    if let Some(before) = query.banned_before {
+        conditions.push(doc! { "bannedTime": { "$lt": before.timestamp_millis() } });
+    }
+    if let Some(text) = &query.reason_contains {
+        conditions.push(doc! { "reason": { "$regex": escape_regex(text), "$options": "i" } });
+    }
+    if let Some(cursor) = cursor {
+        let operator = match query.order {
+            SortOrder::Ascending => "$gt",
+            SortOrder::Descending => "$lt"
+        };
+        let condition = match query.sort_by {
+            BanSortField::UserId => doc! { "userId": { operator: cursor.user_id } },
+            sort_by => {
+                let field = sort_field_name(sort_by);
+                doc! { "$or": [
+                    { field: { operator: cursor.sort_value } },
+                    { field: cursor.sort_value, "userId": { operator: cursor.user_id } }
+                ] }
+            }
+        };
+        conditions.push(condition);
+    }
+
+    if conditions.is_empty() {
+        doc! {}
+    } else {
+        doc! { "$and": conditions }
+    }
+}
+
+#[async_trait]
+impl BanStore for MongoStore {
+    async fn find_ban(&self, user_id: u64) -> Result<Option<BanEntry>, crate::Error> {
+        Ok(self.bans().find_one(doc! { "userId": user_id as i64 }, None).await?)
+    }
+
+    async fn find_bans(&self, user_ids: &[u64]) -> Result<Vec<BanEntry>, crate::Error> {
+        let user_ids: Vec<i64> = user_ids.iter().map(|user_id| *user_id as i64).collect();
+        let cursor = self.bans().find(doc! { "userId": { "$in": user_ids } }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_bans(&self) -> Result<BanPage, crate::Error> {
+        let mut cursor = self.raw_bans().find(None, None).await?;
+        let mut page = BanPage::default();
+
+        while let Some(document) = cursor.next().await {
+            read_ban_document(document?, &mut page);
+        }
+
+        Ok(page)
+    }
+
+    async fn query_bans(&self, query: &BanQuery, now: DateTime<Utc>) -> Result<BanPage, crate::Error> {
+        let cursor = query.decoded_cursor()?;
+        let page_size = query.page_size();
+        let direction = match query.order {
+            SortOrder::Ascending => 1,
+            SortOrder::Descending => -1
+        };
+        let sort = match query.sort_by {
+            BanSortField::UserId => doc! { "userId": direction },
+            sort_by => doc! { sort_field_name(sort_by): direction, "userId": direction }
+        };
+        let options = FindOptions::builder().sort(sort).limit(page_size as i64 + 1).build();
+
+        let mut documents: Vec<Document> = self.raw_bans()
+            .find(ban_query_filter(query, cursor, now), options)
+            .await?
+            .try_collect()
+            .await?;
+        let has_more = documents.len() > page_size as usize;
+        documents.truncate(page_size as usize);
+
+        let sort_field = sort_field_name(query.sort_by);
+        let mut page = BanPage::default();
+        let mut last_position: Option<BanCursor> = None;
+        for document in documents {
+            if let (Some(sort_value), Some(user_id)) = (lenient_i64(&document, sort_field), lenient_i64(&document, "userId")) {
+                last_position = Some(BanCursor { sort_value, user_id });
+            }
+            read_ban_document(document, &mut page);
+        }
+        page.next_cursor = last_position.filter(|_| has_more).map(|position| position.encode());
+
+        Ok(page)
+    }
+
+    async fn upsert_ban(&self, entry: BanEntry) -> Result<(), crate::Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.bans().replace_one(doc! { "userId": entry.user_id }, entry, options).await?;
+        Ok(())
+    }
+
+    async fn upsert_bans(&self, entries: Vec<BanEntry>) -> Result<Vec<(i64, String)>, crate::Error> {
+        let mut failures = Vec::new();
+
+        // One update command per batch, holding an upsert statement for every entry.
+        for batch in entries.chunks(UPSERT_BATCH_SIZE) {
+            let mut updates = Vec::with_capacity(batch.len());
+            for entry in batch {
+                updates.push(doc! { "q": { "userId": entry.user_id }, "u": bson::to_document(entry)?, "upsert": true });
+            }
+            let command = doc! { "update": &self.collections.bans, "updates": updates, "ordered": false };
+            let response = match self.database.run_command(command, None).await {
+                Ok(response) => response,
+                Err(err) => {
+                    failures.extend(batch.iter().map(|entry| (entry.user_id, err.to_string())));
+                    continue
+                }
+            };
+
+            // Failed statements are reported by their position in the batch.
+            let write_errors = response.get_array("writeErrors").map(|errors| errors.as_slice()).unwrap_or_default();
+            for write_error in write_errors.iter().filter_map(Bson::as_document) {
+                if let Some(entry) = lenient_i64(write_error, "index").and_then(|index| batch.get(index as usize)) {
+                    let message = write_error.get_str("errmsg").unwrap_or("Write failed.");
+                    failures.push((entry.user_id, message.to_string()));
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+
+    async fn delete_ban(&self, user_id: u64) -> Result<bool, crate::Error> {
+        let result = self.bans().delete_one(doc! { "userId": user_id as i64 }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn delete_bans(&self, user_ids: &[u64]) -> Result<u64, crate::Error> {
+        let user_ids: Vec<i64> = user_ids.iter().map(|user_id| *user_id as i64).collect();
+        let result = self.bans().delete_many(doc! { "userId": { "$in": user_ids } }, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_expired_bans(&self, now: DateTime<Utc>) -> Result<Vec<BanEntry>, crate::Error> {
+        // Legacy entries store `bannedUntil` in the wrong unit, so they are compared after correcting it.
+        let filter = doc! { "$and": [
+            temporary_ban_filter(),
+            { "$expr": { "$lte": [effective_banned_until(), now.timestamp_millis()] } }
+        ] };
+        let expired: Vec<BanEntry> = self.bans().find(filter.clone(), None).await?.try_collect().await?;
+        if expired.is_empty() {
+            return Ok(expired)
+        }
+
+        let user_ids: Vec<i64> = expired.iter().map(|entry| entry.user_id).collect();
+        let mut delete_filter = filter;
+        delete_filter.insert("userId", doc! { "$in": user_ids });
+        self.bans().delete_many(delete_filter, None).await?;
+        Ok(expired)
+    }
+
+    async fn migrate_legacy_bans(&self) -> Result<u64, crate::Error> {
+        let mut cursor = self.bans().find(doc! { "schemaVersion": { "$exists": false } }, None).await?;
+        let mut migrated: u64 = 0;
+
+        while let Some(entry) = cursor.next().await {
+            let entry = entry?;
+            self.bans().replace_one(doc! { "userId": entry.user_id }, entry, None).await?;
+            migrated += 1;
+        }
+
+        Ok(migrated)
+    }
+}
+
+#[async_trait]
+impl HistoryStore for MongoStore {
+    async fn append_history(&self, record: ModerationRecord) -> Result<(), crate::Error> {
+        self.history().insert_one(record, None).await?;
+        Ok(())
+    }
+
+    async fn append_history_batch(&self, records: Vec<ModerationRecord>) -> Result<(), crate::Error> {
+        if !records.is_empty() {
+            self.history().insert_many(records, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn history_for_user(&self, user_id: u64) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let options = FindOptions::builder().sort(doc! { "time": 1 }).build();
+        let cursor = self.history().find(doc! { "userId": user_id as i64 }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_history(&self) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let options = FindOptions::builder().sort(doc! { "time": 1 }).build();
+        let cursor = self.history().find(None, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn history_for_moderator(&self, moderator: &str, limit: u32) -> Result<Vec<ModerationRecord>, crate::Error> {
+        let options = FindOptions::builder().sort(doc! { "time": -1 }).limit(limit as i64).build();
+        let cursor = self.history().find(doc! { "moderator": moderator }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+}
+
+#[async_trait]
+impl RestrictionStore for MongoStore {
+    async fn upsert_restriction(&self, restriction: Restriction) -> Result<(), crate::Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        let filter = doc! { "userId": restriction.user_id, "scope": bson::to_bson(&restriction.scope)? };
+        self.restrictions().replace_one(filter, restriction, options).await?;
+        Ok(())
+    }
+
+    async fn restrictions_for_user(&self, user_id: u64) -> Result<Vec<Restriction>, crate::Error> {
+        let cursor = self.restrictions().find(doc! { "userId": user_id as i64 }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn delete_restriction(&self, user_id: u64, scope: RestrictionScope) -> Result<Option<Restriction>, crate::Error> {
+        let filter = doc! { "userId": user_id as i64, "scope": bson::to_bson(&scope)? };
+        Ok(self.restrictions().find_one_and_delete(filter, None).await?)
+    }
+}
+
+#[async_trait]
+impl AppealStore for MongoStore {
+    async fn insert_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error> {
+        match self.appeals().insert_one(appeal, None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into())
+        }
+    }
+
+    async fn find_appeal(&self, appeal_id: &str) -> Result<Option<BanAppeal>, crate::Error> {
+        Ok(self.appeals().find_one(doc! { "appealId": appeal_id }, None).await?)
+    }
+
+    async fn appeals_for_user(&self, user_id: u64) -> Result<Vec<BanAppeal>, crate::Error> {
+        let options = FindOptions::builder().sort(doc! { "submittedAt": 1 }).build();
+        let cursor = self.appeals().find(doc! { "userId": user_id as i64 }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn pending_appeals(&self, limit: u32) -> Result<Vec<BanAppeal>, crate::Error> {
+        let options = FindOptions::builder().sort(doc! { "submittedAt": 1 }).limit(limit as i64).build();
+        let status = bson::to_bson(&AppealStatus::Pending)?;
+        let cursor = self.appeals().find(doc! { "status": status }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn update_appeal(&self, appeal: BanAppeal) -> Result<bool, crate::Error> {
+        let filter = doc! { "appealId": &appeal.id, "status": bson::to_bson(&AppealStatus::Pending)? };
+        let result = self.appeals().replace_one(filter, appeal, None).await?;
+        Ok(result.matched_count > 0)
+    }
+}
+
+#[async_trait]
+impl LinkStore for MongoStore {
+    async fn upsert_link(&self, link: AccountLink) -> Result<(), crate::Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        let filter = doc! { "userId": link.user_id, "linkedUserId": link.linked_user_id };
+        self.links().replace_one(filter, link, options).await?;
+        Ok(())
+    }
+
+    async fn links_for_user(&self, user_id: u64) -> Result<Vec<AccountLink>, crate::Error> {
+        let user_id = user_id as i64;
+        let cursor = self.links().find(doc! { "$or": [{ "userId": user_id }, { "linkedUserId": user_id }] }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn delete_link(&self, user_id: u64, linked_user_id: u64) -> Result<bool, crate::Error> {
+        let filter = doc! { "userId": user_id as i64, "linkedUserId": linked_user_id as i64 };
+        let result = self.links().delete_one(filter, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MongoStore {
+    async fn find_api_key(&self, value: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys().find_one(doc! { "value": value }, None).await?)
+    }
+
+    async fn find_api_key_by_owner(&self, roblox_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys().find_one(doc! { "assignOwner": roblox_id }, None).await?)
+    }
+
+    async fn api_keys_for_owner(&self, roblox_id: &str) -> Result<Vec<ApiKey>, crate::Error> {
+        let cursor = self.api_keys().find(doc! { "assignOwner": roblox_id }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_api_key_by_discord_user(&self, discord_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys().find_one(doc! { "associatedDiscordUser": discord_id }, None).await?)
+    }
+
+    async fn api_keys_for_discord_user(&self, discord_id: &str) -> Result<Vec<ApiKey>, crate::Error> {
+        let cursor = self.api_keys().find(doc! { "associatedDiscordUser": discord_id }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn insert_api_key(&self, key: ApiKey) -> Result<bool, crate::Error> {
+        match self.api_keys().insert_one(key, None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into())
+        }
+    }
+
+    async fn find_api_key_by_id(&self, key_id: &str) -> Result<Option<ApiKey>, crate::Error> {
+        Ok(self.api_keys().find_one(doc! { "keyId": key_id }, None).await?)
+    }
+
+    async fn api_keys_needing_migration(&self) -> Result<Vec<ApiKey>, crate::Error> {
+        let filter = doc! { "$or": [{ "hashed": { "$ne": true } }, { "keyId": { "$in": [null, ""] } }] };
+        let cursor = self.api_keys().find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn update_api_key(&self, current: &ApiKey, changes: ApiKeyChanges) -> Result<ApiKeyUpdate, crate::Error> {
+        // Keys stored before IDs or versions existed have no `keyId` or `version` field at all.
+        let key_id = match current.key_id.as_str() {
+            "" => Bson::Document(doc! { "$in": [null, ""] }),
+            key_id => Bson::String(key_id.to_string())
+        };
+        let version = match current.version {
+            0 => Bson::Document(doc! { "$in": [null, 0_i64] }),
+            version => bson::to_bson(&version)?
+        };
+        let filter = doc! { "keyId": key_id, "value": &current.value, "version": version };
+        let mut update = doc! { "$inc": { "version": 1_i64 } };
+        let fields = api_key_changes_document(changes)?;
+        if !fields.is_empty() {
+            update.insert("$set", fields);
+        }
+        match self.api_keys().update_one(filter, update, None).await {
+            Ok(result) if result.matched_count == 0 => Ok(ApiKeyUpdate::Stale),
+            Ok(_) => Ok(ApiKeyUpdate::Updated),
+            Err(err) if is_duplicate_key(&err) => Ok(ApiKeyUpdate::DuplicateValue),
+            Err(err) => Err(err.into())
+        }
+    }
+
+    async fn record_api_key_usage(&self, value: &str, time: DateTime<Utc>) -> Result<(), crate::Error> {
+        let update = doc! {
+            "$inc": { "totalRequests": 1_i64, format!("dailyRequests.{}", usage_day(time.date_naive())): 1_i64 },
+            "$set": { "lastUsed": time.timestamp_millis() }
+        };
+        self.api_keys().update_one(doc! { "value": value }, update, None).await?;
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, key_id: &str) -> Result<bool, crate::Error> {
+        let result = self.api_keys().delete_one(doc! { "keyId": key_id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    /// Evaluates the aggregation expressions the ban filters use, `None` standing for a missing field.
+    fn evaluate(expression: &Bson, document: &Document) -> Option<Bson> {
+        let values = |arguments: &Bson| -> Vec<Option<Bson>> {
+            match arguments {
+                Bson::Array(operands) => operands.iter().map(|operand| evaluate(operand, document)).collect(),
+                operand => vec![evaluate(operand, document)]
+            }
+        };
+        let numbers = |arguments: &Bson| -> Vec<f64> {
+            values(arguments).iter().map(|operand| operand.as_ref().and_then(bson_to_f64).expect("not a number")).collect()
+        };
+
+        let (operator, arguments) = match expression {
+            Bson::String(path) if path.starts_with('$') => return document.get(&path[1..]).cloned(),
+            Bson::Document(expression) if expression.len() == 1 => expression.iter().next().unwrap(),
+            literal => return Some(literal.clone())
+        };
+        let result = match operator.as_str() {
+            "$cond" => {
+                let arguments = arguments.as_array().unwrap();
+                let branch = if is_true(evaluate(&arguments[0], document)) { &arguments[1] } else { &arguments[2] };
+                return evaluate(branch, document)
+            },
+            "$type" => Bson::String(match evaluate(arguments, document) {
+                None => "missing".to_string(),
+                Some(value) => format!("{:?}", value.element_type())
+            }),
+            "$eq" => {
+                let values = values(arguments);
+                Bson::Boolean(values[0] == values[1])
+            },
+            "$gt" | "$lte" | "$lt" => {
+                let values = numbers(arguments);
+                Bson::Boolean(match operator.as_str() {
+                    "$gt" => values[0] > values[1],
+                    "$lte" => values[0] <= values[1],
+                    _ => values[0] < values[1]
+                })
+            },
+            "$add" => Bson::Double(numbers(arguments).iter().sum()),
+            "$multiply" => Bson::Double(numbers(arguments).iter().product()),
+            "$subtract" | "$divide" => {
+                let values = numbers(arguments);
+                Bson::Double(if operator == "$subtract" { values[0] - values[1] } else { values[0] / values[1] })
+            },
+            "$trunc" => Bson::Double(numbers(arguments)[0].trunc()),
+            operator => panic!("{} is not supported", operator)
+        };
+        Some(result)
+    }
+
+    fn bson_to_f64(value: &Bson) -> Option<f64> {
+        match value {
+            Bson::Double(value) => Some(*value),
+            value => bson_to_i64(value).map(|value| value as f64)
+        }
+    }
+
+    fn is_true(value: Option<Bson>) -> bool {
+        matches!(value, Some(Bson::Boolean(true)))
+    }
+
+    /// Whether `document` matches `filter`, for the query operators the ban filters use.
+    fn filter_matches(filter: &Document, document: &Document) -> bool {
+        filter.iter().all(|(key, condition)| {
+            let subfilters = || condition.as_array().unwrap().iter().map(|filter| filter.as_document().unwrap());
+            match key.as_str() {
+                "$and" => subfilters().all(|filter| filter_matches(filter, document)),
+                "$or" => subfilters().any(|filter| filter_matches(filter, document)),
+                "$nor" => !subfilters().any(|filter| filter_matches(filter, document)),
+                "$expr" => is_true(evaluate(condition, document)),
+                field => match condition {
+                    Bson::Document(condition) => {
+                        let exists = condition.get_bool("$exists").expect("only $exists is supported");
+                        document.contains_key(field) == exists
+                    },
+                    value => document.get(field).and_then(bson_to_f64) == bson_to_f64(value)
+                }
+            }
+        })
+    }
+
+    fn legacy_document(user_id: i64, banned_time: i64, duration_in_minutes: i32) -> Document {
+        let banned_until = if duration_in_minutes != -1 {
+            banned_time + duration_in_minutes.wrapping_mul(60) as i64
+        } else {
+            -1
+        };
+        doc! { "userId": user_id, "bannedTime": banned_time, "bannedUntil": banned_until, "moderator": "Moderator", "reason": "Exploiting" }
+    }
+
+    #[test]
+    fn status_filters_read_legacy_bans_like_ban_entries() {
+        let now = Utc::now();
+        let banned_time = (now - Duration::minutes(30)).timestamp_millis();
+        let current = |user_id: i64, banned_until: Option<DateTime<Utc>>| {
+            let entry = BanEntry {
+                user_id,
+                banned_time: now - Duration::minutes(30),
+                banned_until,
+                moderator: "Moderator".to_string(),
+                reason: "Exploiting".to_string()
+            };
+            bson::to_document(&entry).unwrap()
+        };
+        let documents = [
+            legacy_document(1, banned_time, -1),
+            // Read literally, legacy bans end seconds after they start, so the 60 minute one would count as expired.
+            legacy_document(2, banned_time, 20),
+            legacy_document(3, banned_time, 60),
+            legacy_document(4, banned_time, 40_000_000),
+            legacy_document(5, banned_time, 0),
+            current(6, None),
+            current(7, Some(now - Duration::minutes(1))),
+            current(8, Some(now + Duration::minutes(1)))
+        ];
+
+        for status in [BanStatus::Active, BanStatus::Expired] {
+            for permanent in [None, Some(true), Some(false)] {
+                let query = BanQuery { status, permanent, ..BanQuery::default() };
+                let filter = ban_query_filter(&query, None, now);
+                for document in &documents {
+                    let entry: BanEntry = bson::from_document(document.clone()).unwrap();
+                    assert_eq!(
+                        filter_matches(&filter, document),
+                        query.matches(&entry, now),
+                        "{:?} with {:?}, permanent {:?}", document, status, permanent
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{ Deserialize, Serialize };
+
+use crate::{Backend, BackendError};
+use super::ban_query::InvalidBanDocument;
+use super::history::{ModerationAction, ModerationRecord};
+use super::moderation::BanEntry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferFormat {
+    Json,
+    Csv
+}
+
+/// A ban as exported and imported. Times are RFC 3339, `bannedUntil` is empty for permanent bans.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct BanRow {
+    #[serde(rename = "userId")]
+    user_id: u64,
+    #[serde(rename = "bannedTime")]
+    banned_time: DateTime<Utc>,
+    #[serde(rename = "bannedUntil")]
+    banned_until: Option<DateTime<Utc>>,
+    moderator: String,
+    reason: String
+}
+
+/// A history record flattened for CSV. The bans before and after the action are left out.
+#[derive(Serialize)]
+struct HistoryRow<'a> {
+    #[serde(rename = "userId")]
+    user_id: i64,
+    action: ModerationAction,
+    moderator: &'a str,
+    reason: &'a str,
+    time: DateTime<Utc>
+}
+
+impl From<BanEntry> for BanRow {
+    fn from(entry: BanEntry) -> Self {
+        Self {
+            user_id: entry.user_id as u64,
+            banned_time: entry.banned_time,
+            banned_until: entry.banned_until,
+            moderator: entry.moderator,
+            reason: entry.reason
+        }
+    }
+}
+
+impl BanRow {
+    fn validate(self) -> Result<BanEntry, String> {
+        if self.user_id == 0 || self.user_id > i64::MAX as u64 {
+            return Err(format!("User ID {} is out of range.", self.user_id))
+        }
+        if self.banned_until.is_some_and(|until| until <= self.banned_time) {
+            return Err("Ban ends before it starts.".to_string())
+        }
+        if self.moderator.trim().is_empty() {
+            return Err("Moderator is empty.".to_string())
+        }
+
+        Ok(BanEntry {
+            user_id: self.user_id as i64,
+            banned_time: self.banned_time,
+            banned_until: self.banned_until,
+            moderator: self.moderator,
+            reason: self.reason
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BanExport {
+    pub data: String,
+    /// Stored bans that could not be read, and are missing from `data`.
+    pub invalid_documents: Vec<InvalidBanDocument>
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the stored ban.
+    #[default]
+    Skip,
+    /// Replace the stored ban with the imported one.
+    Overwrite
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    pub on_conflict: ConflictPolicy,
+    /// Validate and report without writing anything.
+    pub dry_run: bool
+}
+
+/// A row that could not be imported. `row` counts data rows from 1, not counting a CSV header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidImportRow {
+    pub row: usize,
+    pub error: String
+}
+
+/// An imported ban for a player who already has a different stored ban.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportConflict {
+    pub existing: BanEntry,
+    pub incoming: BanEntry,
+    pub overwritten: bool
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    /// Players whose ban was written, including overwritten conflicts.
+    pub imported: Vec<u64>,
+    /// Players whose stored ban is identical to the imported one.
+    pub unchanged: Vec<u64>,
+    /// Players listed more than once. Only their first row is used.
+    pub duplicates: Vec<u64>,
+    pub conflicts: Vec<ImportConflict>,
+    pub invalid: Vec<InvalidImportRow>,
+    /// Bans that passed validation but could not be written, with the storage error.
+    pub failed: Vec<(u64, String)>
+}
+
+fn transfer_error(err: impl ToString) -> BackendError {
+    BackendError::BanTransfer(err.to_string())
+}
+
+fn to_csv<T: Serialize>(rows: impl IntoIterator<Item = T>) -> Result<String, crate::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(transfer_error)?;
+    }
+    let bytes = writer.into_inner().map_err(transfer_error)?;
+    String::from_utf8(bytes).map_err(transfer_error)
+}
+
+/// Every row of the input, each either parsed or with its parse error.
+fn parse_rows(data: &str, format: TransferFormat) -> Result<Vec<Result<BanRow, String>>, crate::Error> {
+    match format {
+        TransferFormat::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_str(data).map_err(transfer_error)?;
+            Ok(values.into_iter().map(|value| serde_json::from_value(value).map_err(|err| err.to_string())).collect())
+        },
+        TransferFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data.as_bytes());
+            Ok(reader.deserialize().map(|row| row.map_err(|err: csv::Error| err.to_string())).collect())
+        }
+    }
+}
+
+impl Backend {
+    pub async fn export_bans(&self, format: TransferFormat) -> Result<BanExport, crate::Error> {
+        let mut bans = self.get_ban_collection().await?;
+        bans.entries.sort_by_key(|entry| entry.user_id);
+        let rows = bans.entries.into_iter().map(BanRow::from);
+
+        let data = match format {
+            TransferFormat::Json => serde_json::to_string_pretty(&rows.collect::<Vec<BanRow>>()).map_err(transfer_error)?,
+            TransferFormat::Csv => to_csv(rows)?
+        };
+        Ok(BanExport { data, invalid_documents: bans.invalid_documents })
+    }
+
+    /// The whole moderation history, oldest first. CSV only keeps who did what to whom, when and why.
+    pub async fn export_history(&self, format: TransferFormat) -> Result<String, crate::Error> {
+        let history = self.storage()?.list_history().await?;
+
+        match format {
+            TransferFormat::Json => serde_json::to_string_pretty(&history).map_err(transfer_error),
+            TransferFormat::Csv => to_csv(history.iter().map(|record| HistoryRow {
+                user_id: record.user_id,
+                action: record.action,
+                moderator: &record.moderator,
+                reason: &record.reason,
+                time: record.time
+            }))
+        }
+    }
+
+    /// Imports bans exported by `export_bans`, or written by hand in the same format. Invalid rows and
+    /// repeated user IDs are skipped and reported, conflicts with stored bans are handled as `options` says.
+    /// Written bans are recorded in the moderation history under `moderator`.
+    pub async fn import_bans(&self, data: &str, format: TransferFormat, options: ImportOptions, moderator: &str) -> Result<ImportReport, crate::Error> {
+        let mut report = ImportReport::default();
+        let mut seen = HashSet::new();
+        let mut incoming: Vec<BanEntry> = Vec::new();
+
+        for (index, row) in parse_rows(data, format)?.into_iter().enumerate() {
+            match row.and_then(BanRow::validate) {
+                Ok(entry) => {
+                    if seen.insert(entry.user_id) {
+                        incoming.push(entry);
+                    } else {
+                        report.duplicates.push(entry.user_id as u64);
+                    }
+                },
+                Err(error) => report.invalid.push(InvalidImportRow { row: index + 1, error })
+            }
+        }
+
+        let user_ids: Vec<u64> = incoming.iter().map(|entry| entry.user_id as u64).collect();
+        let existing: HashMap<i64, BanEntry> = self.storage()?.find_bans(&user_ids).await?
+            .into_iter()
+            .map(|entry| (entry.user_id, entry))
+            .collect();
+
+        let mut to_write: Vec<(Option<BanEntry>, BanEntry)> = Vec::new();
+        for entry in incoming {
+            match existing.get(&entry.user_id) {
+                None => to_write.push((None, entry)),
+                Some(stored) if *stored == entry => report.unchanged.push(entry.user_id as u64),
+                Some(stored) => {
+                    let overwritten = options.on_conflict == ConflictPolicy::Overwrite;
+                    report.conflicts.push(ImportConflict { existing: stored.clone(), incoming: entry.clone(), overwritten });
+                    if overwritten {
+                        to_write.push((Some(stored.clone()), entry));
+                    }
+                }
+            }
+        }
+
+        if options.dry_run || to_write.is_empty() {
+            report.imported = to_write.iter().map(|(_, entry)| entry.user_id as u64).collect();
+            return Ok(report)
+        }
+
+        let entries: Vec<BanEntry> = to_write.iter().map(|(_, entry)| entry.clone()).collect();
+        let failures: HashMap<i64, String> = self.storage()?.upsert_bans(entries).await?.into_iter().collect();
+        let now = Utc::now();
+        let mut records = Vec::new();
+        for (previous, entry) in to_write {
+            if let Some(error) = failures.get(&entry.user_id) {
+                report.failed.push((entry.user_id as u64, error.clone()));
+                continue
+            }
+            report.imported.push(entry.user_id as u64);
+            records.push(ModerationRecord {
+                user_id: entry.user_id,
+                action: ModerationAction::Ban,
+                moderator: moderator.to_string(),
+                reason: "Imported.".to_string(),
+                time: now,
+                previous,
+                current: Some(entry),
+                restriction: None
+            });
+        }
+        self.storage()?.append_history_batch(records).await?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use futures::executor::block_on;
+
+    use crate::test_support::backend;
+    use super::*;
+
+    fn banned_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn ban(user_id: i64, banned_until: Option<DateTime<Utc>>, reason: &str) -> BanEntry {
+        BanEntry { user_id, banned_time: banned_time(), banned_until, moderator: "Moderator".to_string(), reason: reason.to_string() }
+    }
+
+    fn bans() -> Vec<BanEntry> {
+        vec![
+            ban(1, None, "Exploiting, repeatedly"),
+            ban(2, Some(banned_time() + Duration::days(7)), "Spam"),
+            ban(3, Some(banned_time() + Duration::milliseconds(1500)), "Said \"hi\"")
+        ]
+    }
+
+    fn backend_with(entries: &[BanEntry]) -> Backend {
+        let backend = backend();
+        block_on(backend.storage().unwrap().upsert_bans(entries.to_vec())).unwrap();
+        backend
+    }
+
+    fn stored_bans(backend: &Backend) -> Vec<BanEntry> {
+        let mut entries = block_on(backend.get_ban_collection()).unwrap().entries;
+        entries.sort_by_key(|entry| entry.user_id);
+        entries
+    }
+
+    fn import(backend: &Backend, data: &str, format: TransferFormat, options: ImportOptions) -> ImportReport {
+        block_on(backend.import_bans(data, format, options, "Importer")).unwrap()
+    }
+
+    #[test]
+    fn exported_bans_import_unchanged() {
+        for format in [TransferFormat::Json, TransferFormat::Csv] {
+            let export = block_on(backend_with(&bans()).export_bans(format)).unwrap();
+            assert!(export.invalid_documents.is_empty());
+
+            let backend = backend();
+            let report = import(&backend, &export.data, format, ImportOptions::default());
+            assert_eq!(report.imported, vec![1, 2, 3], "{:?}", format);
+            assert!(report.invalid.is_empty(), "{:?}: {:?}", format, report.invalid);
+            assert_eq!(stored_bans(&backend), bans(), "{:?}", format);
+
+            let history = block_on(backend.get_player_moderation_history(2)).unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!((history[0].action, history[0].moderator.as_str()), (ModerationAction::Ban, "Importer"));
+            assert_eq!(history[0].current, Some(bans()[1].clone()));
+
+            let report = import(&backend, &export.data, format, ImportOptions::default());
+            assert_eq!(report.unchanged, vec![1, 2, 3]);
+            assert!(report.imported.is_empty());
+        }
+    }
+
+    #[test]
+    fn csv_leaves_the_end_of_permanent_bans_empty() {
+        let export = block_on(backend_with(&bans()[..1]).export_bans(TransferFormat::Csv)).unwrap();
+        let mut lines = export.data.lines();
+        assert_eq!(lines.next(), Some("userId,bannedTime,bannedUntil,moderator,reason"));
+        assert_eq!(lines.next(), Some("1,2024-05-01T12:00:00Z,,Moderator,\"Exploiting, repeatedly\""));
+
+        let backend = backend();
+        let data = "userId, bannedTime, bannedUntil, moderator, reason\n7, 2024-05-01T12:00:00Z, , Moderator, Exploiting\n";
+        assert_eq!(import(&backend, data, TransferFormat::Csv, ImportOptions::default()).imported, vec![7]);
+        assert_eq!(stored_bans(&backend)[0].banned_until, None);
+    }
+
+    #[test]
+    fn reports_duplicate_and_invalid_rows() {
+        let data = "\
+userId,bannedTime,bannedUntil,moderator,reason
+1,2024-05-01T12:00:00Z,,Moderator,First
+0,2024-05-01T12:00:00Z,,Moderator,No user
+2,2024-05-01T12:00:00Z,2024-04-01T12:00:00Z,Moderator,Ends early
+3,2024-05-01T12:00:00Z,,,No moderator
+4,yesterday,,Moderator,Bad time
+1,2024-05-02T12:00:00Z,,Moderator,Second
+-5,2024-05-01T12:00:00Z,,Moderator,Negative
+";
+        let backend = backend();
+        let report = import(&backend, data, TransferFormat::Csv, ImportOptions::default());
+        assert_eq!(report.imported, vec![1]);
+        assert_eq!(report.duplicates, vec![1]);
+        let invalid_rows: Vec<usize> = report.invalid.iter().map(|invalid| invalid.row).collect();
+        assert_eq!(invalid_rows, vec![2, 3, 4, 5, 7]);
+        assert_eq!(report.invalid[0].error, "User ID 0 is out of range.");
+        assert_eq!(report.invalid[1].error, "Ban ends before it starts.");
+        assert_eq!(report.invalid[2].error, "Moderator is empty.");
+        assert_eq!(stored_bans(&backend), vec![ban(1, None, "First")]);
+
+        let json = r#"[{ "userId": 1, "bannedTime": "2024-05-01T12:00:00Z", "bannedUntil": null, "moderator": "Moderator", "reason": "" }, { "userId": "two" }]"#;
+        let report = import(&backend, json, TransferFormat::Json, ImportOptions::default());
+        assert_eq!(report.invalid.iter().map(|invalid| invalid.row).collect::<Vec<_>>(), vec![2]);
+
+        let result = block_on(backend.import_bans("{}", TransferFormat::Json, ImportOptions::default(), "Importer"));
+        assert!(matches!(result, Err(BackendError::BanTransfer(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn handles_conflicts_as_told() {
+        let stored = ban(1, None, "Stored");
+        let incoming = vec![ban(1, Some(banned_time() + Duration::days(1)), "Imported"), ban(2, None, "New")];
+        let data = serde_json::to_string(&incoming.iter().cloned().map(BanRow::from).collect::<Vec<_>>()).unwrap();
+        let conflict = |overwritten| ImportConflict { existing: stored.clone(), incoming: incoming[0].clone(), overwritten };
+
+        let backend = backend_with(std::slice::from_ref(&stored));
+        let report = import(&backend, &data, TransferFormat::Json, ImportOptions::default());
+        assert_eq!(report.conflicts, vec![conflict(false)]);
+        assert_eq!(report.imported, vec![2]);
+        assert_eq!(stored_bans(&backend), vec![stored.clone(), incoming[1].clone()]);
+
+        let backend = backend_with(std::slice::from_ref(&stored));
+        let overwrite = ImportOptions { on_conflict: ConflictPolicy::Overwrite, dry_run: false };
+        let report = import(&backend, &data, TransferFormat::Json, overwrite);
+        assert_eq!(report.conflicts, vec![conflict(true)]);
+        assert_eq!(report.imported, vec![1, 2]);
+        assert_eq!(stored_bans(&backend), incoming);
+        let history = block_on(backend.get_player_moderation_history(1)).unwrap();
+        assert_eq!(history[0].previous, Some(stored.clone()));
+
+        let backend = backend_with(std::slice::from_ref(&stored));
+        let dry_run = ImportOptions { on_conflict: ConflictPolicy::Overwrite, dry_run: true };
+        let report = import(&backend, &data, TransferFormat::Json, dry_run);
+        assert_eq!(report.conflicts, vec![conflict(true)]);
+        assert_eq!(report.imported, vec![1, 2]);
+        assert_eq!(stored_bans(&backend), vec![stored]);
+        assert!(block_on(backend.storage().unwrap().list_history()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::database::restrictions::RestrictionScope;
+use crate::id_converter::ShortIdError;
+use crate::roblox::structs::{AssetType, RobloxApiError};
+
+#[derive(Debug)]
+pub enum BackendError {
+    /// Roblox answered with a non-success status code.
+    RobloxApi { status: u16, error: Option<RobloxApiError> },
+    /// Roblox answered, but not in a shape we know how to handle.
+    UnexpectedResponse(String),
+    Http(reqwest::Error),
+    AssetNotForSale,
+    InvalidAssetType(Option<AssetType>),
+    AssetCostsRobux(u64),
+    AssetNotOwned,
+    /// `Backend` was configured with missing or inconsistent settings.
+    InvalidConfig(String),
+    Database(mongodb::error::Error),
+    DatabaseNotConnected,
+    InvalidBanDuration(String),
+    BanNotFound(u64),
+    RestrictionNotFound(u64, RestrictionScope),
+    InvalidQuery(String),
+    InvalidAppeal(String),
+    /// The player's last appeal for this ban was rejected too recently.
+    AppealCooldown { retry_after: DateTime<Utc> },
+    InvalidAccountLink(String),
+    /// Bans or history could not be exported, or the import data is malformed as a whole.
+    BanTransfer(String),
+    /// Every generated API key collided with an existing one.
+    DuplicateApiKey,
+    /// The API key does not exist or is disabled.
+    InvalidApiKey,
+    ApiKeyNotFound(String),
+    /// The key kept being changed by someone else while it was being updated.
+    ApiKeyConflict(String),
+    IdConversion(String),
+    InvalidShortId(ShortIdError),
+    UnknownIdNamespace(String),
+    LuauParse(full_moon::Error),
+    RbxmParse(rbx_binary::DecodeError),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RobloxApi { status, error } => {
+                match error.as_ref().and_then(|info| info.errors.first()) {
+                    Some(err) => write!(f, "Roblox returned error code: {}, message: {}", status, err.message),
+                    None => write!(f, "Roblox returned error code: {}", status)
+                }
+            },
+            Self::UnexpectedResponse(message) => write!(f, "Unexpected response from Roblox: {}", message),
+            Self::Http(err) => write!(f, "HTTP request failed: {}", err),
+            Self::AssetNotForSale => write!(f, "Asset is not for sale."),
+            Self::InvalidAssetType(asset_type) => match asset_type {
+                Some(asset_type) => write!(f, "Asset type is not a Model (got {:?}).", asset_type),
+                None => write!(f, "Asset type is not a Model.")
+            },
+            Self::AssetCostsRobux(price) => write!(f, "Asset costs robux ({}).", price),
+            Self::AssetNotOwned => write!(f, "User does not own asset."),
+            Self::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            Self::Database(err) => write!(f, "Database error: {}", err),
+            Self::DatabaseNotConnected => write!(f, "Database not connected."),
+            Self::InvalidBanDuration(message) => write!(f, "Invalid ban duration: {}", message),
+            Self::BanNotFound(user_id) => write!(f, "User {} is not banned.", user_id),
+            Self::RestrictionNotFound(user_id, scope) => write!(f, "User {} has no {:?} restriction.", user_id, scope),
+            Self::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            Self::InvalidAppeal(message) => write!(f, "Invalid appeal: {}", message),
+            Self::AppealCooldown { retry_after } => write!(f, "Appeal was rejected recently, try again after {}.", retry_after),
+            Self::InvalidAccountLink(message) => write!(f, "Invalid account link: {}", message),
+            Self::BanTransfer(message) => write!(f, "Ban import/export failed: {}", message),
+            Self::DuplicateApiKey => write!(f, "Could not generate a unique API key."),
+            Self::InvalidApiKey => write!(f, "API key is invalid or disabled."),
+            Self::ApiKeyNotFound(key_id) => write!(f, "API key {} does not exist.", key_id),
+            Self::ApiKeyConflict(key_id) => write!(f, "API key {} was changed concurrently, try again.", key_id),
+            Self::IdConversion(message) => write!(f, "ID conversion failed: {}", message),
+            Self::InvalidShortId(err) => write!(f, "Invalid short ID: {}", err),
+            Self::UnknownIdNamespace(name) => write!(f, "ID namespace {} does not exist.", name),
+            Self::LuauParse(err) => write!(f, "Failed to parse Luau source: {}", err),
+            Self::RbxmParse(err) => write!(f, "Failed to parse rbxm: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(err) => Some(err),
+            Self::Database(err) => Some(err),
+            Self::LuauParse(err) => Some(err),
+            Self::RbxmParse(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Http(err)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for BackendError {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        Self::Database(err.into())
+    }
+}
+
+impl From<mongodb::error::Error> for BackendError {
+    fn from(err: mongodb::error::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<full_moon::Error> for BackendError {
+    fn from(err: full_moon::Error) -> Self {
+        Self::LuauParse(err)
+    }
+}
+
+impl From<rbx_binary::DecodeError> for BackendError {
+    fn from(err: rbx_binary::DecodeError) -> Self {
+        Self::RbxmParse(err)
+    }
+}
@@ -1,66 +1,722 @@
-use crate::utils;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::BackendError;
+
+const FEISTEL_ROUNDS: usize = 8;
+
+/// Most suggestions returned with `ShortIdError::TypoDetected`.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Why a short code could not be decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShortIdError {
+    Empty,
+    /// With checksums on, a code needs at least one character besides the check character.
+    TooShort,
+    /// `index` counts characters from the start of the code.
+    InvalidCharacter { character: char, index: usize },
+    /// The code is well-formed but stands for a number outside the `u64` range.
+    OutOfRange,
+    /// The check character does not match. `suggestions` holds valid codes one swap of neighbouring
+    /// characters or one wrong character away, swaps first.
+    TypoDetected { suggestions: Vec<String> }
+}
+
+impl fmt::Display for ShortIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Code is empty."),
+            Self::TooShort => write!(f, "Code is too short."),
+            Self::InvalidCharacter { character, index } => write!(f, "Character {:?} at position {} is not part of the ID alphabet.", character, index),
+            Self::OutOfRange => write!(f, "Code is out of range."),
+            Self::TypoDetected { suggestions } if suggestions.is_empty() => write!(f, "Code contains a typo."),
+            Self::TypoDetected { suggestions } => write!(f, "Code contains a typo, did you mean {}?", suggestions.join(" or "))
+        }
+    }
+}
+
+/// How `IDConverter::decode` reads a code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    #[default]
+    Standard,
+    /// Like `Standard`, but characters outside the alphabet are first replaced by the alphabet character of
+    /// the same letter in the other case, or of the same confusable group.
+    Lenient,
+    /// Codes issued before the codec was rewritten, which ran the decimal digits of the ID through the
+    /// number alphabet first. Decodes exactly like the old converter, invalid characters aside.
+    Legacy
+}
+
+/// Groups of characters that are easily mistaken for each other, like `0`, `O` and `o`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct ConfusableTable {
+    pub groups: Vec<String>
+}
+
+impl Default for ConfusableTable {
+    fn default() -> Self {
+        Self { groups: ["0Oo", "1Il|", "2Zz", "5Ss", "8B"].map(String::from).to_vec() }
+    }
+}
+
+/// Characters of an alphabet that `DecodeMode::Lenient` cannot tell apart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AlphabetWarning {
+    Confusable { first: char, second: char },
+    /// Both cases of the same letter.
+    CaseVariants { first: char, second: char }
+}
+
+impl fmt::Display for AlphabetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Confusable { first, second } => write!(f, "{:?} and {:?} are easily confused.", first, second),
+            Self::CaseVariants { first, second } => write!(f, "{:?} and {:?} only differ in case.", first, second)
+        }
+    }
+}
+
+pub(crate) fn other_case(character: char) -> Option<char> {
+    let other: Vec<char> = if character.is_lowercase() {
+        character.to_uppercase().collect()
+    } else {
+        character.to_lowercase().collect()
+    };
+    match other[..] {
+        [other] if other != character => Some(other),
+        _ => None
+    }
+}
+
+/// Converts IDs to short codes and back. A code is the ID plus one in bijective base-k over the alphabet,
+/// least significant character first, so every `u64` has exactly one code and every code decodes to at most
+/// one `u64`. With checksums on, a Luhn mod N check character is appended, which catches every single wrong
+/// character and most swaps of neighbouring characters, as long as the alphabet has an even number of
+/// characters, see `validate_checksum_alphabet`.
 pub struct IDConverter {
-    alphabets: String,
-    numbers: String
+    alphabets: Vec<char>,
+    numbers: Vec<char>,
+    checksum: bool,
+    obfuscation: Option<Feistel>,
+    confusables: ConfusableTable,
+    /// Replacements `DecodeMode::Lenient` applies to characters outside the alphabet.
+    normalization: HashMap<char, char>
+}
+
+fn splitmix64(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+/// Keyed permutation of `u64` so neighbouring IDs get unrelated codes. Not meant as encryption, only to
+/// stop codes from being enumerated.
+struct Feistel {
+    round_keys: [u64; FEISTEL_ROUNDS]
+}
+
+impl Feistel {
+    fn new(key: &str) -> Self {
+        let digest = Sha256::digest(key.as_bytes());
+        let mut state = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        let mut round_keys = [0; FEISTEL_ROUNDS];
+        for (index, round_key) in round_keys.iter_mut().enumerate() {
+            let chunk = &digest[(index % 4) * 8..(index % 4) * 8 + 8];
+            state = splitmix64(state ^ u64::from_le_bytes(chunk.try_into().unwrap()));
+            *round_key = state;
+        }
+        Self { round_keys }
+    }
+
+    fn round(right: u32, round_key: u64) -> u32 {
+        splitmix64(right as u64 ^ round_key) as u32
+    }
+
+    fn permute(&self, value: u64) -> u64 {
+        let (mut left, mut right) = ((value >> 32) as u32, value as u32);
+        for round_key in self.round_keys {
+            (left, right) = (right, left ^ Self::round(right, round_key));
+        }
+        ((left as u64) << 32) | right as u64
+    }
+
+    fn unpermute(&self, value: u64) -> u64 {
+        let (mut left, mut right) = ((value >> 32) as u32, value as u32);
+        for round_key in self.round_keys.iter().rev() {
+            (left, right) = (right ^ Self::round(left, *round_key), left);
+        }
+        ((left as u64) << 32) | right as u64
+    }
 }
 
 impl IDConverter {
-    fn convert_base(&self, input: String, translator: &String, convert_translator: &String, shift_left: bool) -> String {
-        let input = input.as_str();
-        let translator = translator.as_str();
-        let convert_translator = convert_translator.as_str();
+    /// Checks that the alphabets can round-trip IDs: `numbers` must be the ten decimal digits in any order,
+    /// `alphabets` needs at least two characters, and neither may repeat characters or share them.
+    pub fn validate_alphabets(alphabets: &str, numbers: &str) -> Result<(), crate::Error> {
+        let invalid = |message: &str| Err(BackendError::InvalidConfig(message.to_string()));
 
-        let mut base_x: usize = 0;
-        let base_value: usize = translator.chars().count();
+        Self::validate_alphabet(alphabets).map_err(BackendError::InvalidConfig)?;
+        if numbers.chars().count() != 10 || !numbers.chars().all(|c| c.is_ascii_digit()) {
+            return invalid("ID number alphabet must contain each decimal digit exactly once.")
+        }
+
+        let seen: HashSet<char> = alphabets.chars().collect();
+        let mut seen_numbers = HashSet::new();
+        if !numbers.chars().all(|c| seen_numbers.insert(c)) {
+            return invalid("ID number alphabet contains duplicate characters.")
+        }
+        if !seen.is_disjoint(&seen_numbers) {
+            return invalid("ID alphabet and number alphabet must not share characters.")
+        }
+
+        Ok(())
+    }
 
-        for char in input.chars() {
-            let mut char_index = translator.chars().position(|c| c == char).unwrap_or(0) + 1;
-            char_index -= if shift_left { 1 } else { 0 };
+    /// At least two characters, none repeated.
+    pub fn validate_alphabet(alphabet: &str) -> Result<(), String> {
+        if alphabet.chars().count() < 2 {
+            return Err("ID alphabet must have at least 2 characters.".to_string())
+        }
+        let mut seen = HashSet::new();
+        if !alphabet.chars().all(|c| seen.insert(c)) {
+            return Err("ID alphabet contains duplicate characters.".to_string())
+        }
+        Ok(())
+    }
 
-            base_x = base_x * base_value + char_index;
+    /// An even number of characters. With an odd number, doubling in the Luhn mod N sum maps two characters
+    /// to the same value, so some wrong characters pass the check.
+    pub fn validate_checksum_alphabet(alphabet: &str) -> Result<(), String> {
+        let count = alphabet.chars().count();
+        if !count.is_multiple_of(2) {
+            return Err(format!("ID alphabet needs an even number of characters for check characters, it has {}.", count))
         }
+        Ok(())
+    }
 
-        if base_x != 0 {
-            let mut result = String::new();
-            let new_base_value: usize = convert_translator.chars().count();
+    /// Confusable or case-variant characters the alphabet contains together. Such pairs are fine for
+    /// standard decoding, but `DecodeMode::Lenient` leaves them as they are, and people reading codes out
+    /// loud will mix them up.
+    pub fn alphabet_warnings(alphabets: &str, confusables: &ConfusableTable) -> Vec<AlphabetWarning> {
+        let alphabet: Vec<char> = alphabets.chars().collect();
+        let mut warnings = Vec::new();
 
-            while base_x > 0 {
-                let mut translated_position = base_x % new_base_value;
-                translated_position -= if shift_left { 1 } else { 0 };
-                
-                let translated_char = convert_translator.chars().nth(translated_position);
-                let mut char_to_use: char = '0';
-                if translated_char.is_some() {
-                    char_to_use = translated_char.unwrap();
+        for group in &confusables.groups {
+            let members: Vec<char> = alphabet.iter().copied().filter(|c| group.contains(*c)).collect();
+            for (index, first) in members.iter().enumerate() {
+                for second in &members[index + 1..] {
+                    warnings.push(AlphabetWarning::Confusable { first: *first, second: *second });
                 }
+            }
+        }
+        for (index, first) in alphabet.iter().enumerate() {
+            if let Some(second) = alphabet[index + 1..].iter().find(|c| other_case(*first) == Some(**c)) {
+                warnings.push(AlphabetWarning::CaseVariants { first: *first, second: *second });
+            }
+        }
+        warnings
+    }
 
-                result.push(char_to_use);
-                base_x /= new_base_value;
+    pub fn new(alphabets: &str, numbers: &str) -> Self {
+        Self {
+            alphabets: alphabets.chars().collect(),
+            numbers: numbers.chars().collect(),
+            checksum: false,
+            obfuscation: None,
+            confusables: ConfusableTable::default(),
+            normalization: HashMap::new()
+        }.with_confusables(ConfusableTable::default())
+    }
+
+    /// Replaces the confusable groups used by `DecodeMode::Lenient`. Groups with more than one alphabet
+    /// character are ambiguous and ignored, as are letters whose both cases are in the alphabet.
+    pub fn with_confusables(mut self, confusables: ConfusableTable) -> Self {
+        // Case comes first, a wrongly cased letter is a likelier mistake than a confused one.
+        let mut normalization = HashMap::new();
+        for character in &self.alphabets {
+            if let Some(other) = other_case(*character).filter(|other| !self.alphabets.contains(other)) {
+                normalization.insert(other, *character);
+            }
+        }
+        for group in &confusables.groups {
+            let mut members = group.chars().filter(|c| self.alphabets.contains(c));
+            if let (Some(target), None) = (members.next(), members.next()) {
+                for character in group.chars().filter(|c| *c != target) {
+                    normalization.entry(character).or_insert(target);
+                }
             }
+        }
+
+        self.normalization = normalization;
+        self.confusables = confusables;
+        self
+    }
+
+    pub fn warnings(&self) -> Vec<AlphabetWarning> {
+        Self::alphabet_warnings(&self.alphabets.iter().collect::<String>(), &self.confusables)
+    }
+
+    /// Shuffles IDs with a permutation keyed by `key` before encoding, so codes of consecutive IDs look
+    /// unrelated. Codes then use the full length for every ID. Legacy codes are never shuffled.
+    pub fn with_obfuscation_key(mut self, key: Option<&str>) -> Self {
+        self.obfuscation = key.map(Feistel::new);
+        self
+    }
+
+    /// Whether codes carry a check character. Legacy codes never do.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    fn base(&self) -> u128 {
+        self.alphabets.len() as u128
+    }
+
+    /// Alphabet positions of the code's characters.
+    fn digits(&self, input: &str) -> Result<Vec<usize>, ShortIdError> {
+        if input.is_empty() {
+            return Err(ShortIdError::Empty)
+        }
+        input.chars()
+            .enumerate()
+            .map(|(index, character)| {
+                self.alphabets.iter()
+                    .position(|c| *c == character)
+                    .ok_or(ShortIdError::InvalidCharacter { character, index })
+            })
+            .collect()
+    }
+
+    /// The digits read as bijective base-k, least significant first, where position p stands for p + 1.
+    fn bijective_value(&self, digits: &[usize]) -> Result<u128, ShortIdError> {
+        if digits.is_empty() {
+            return Err(ShortIdError::Empty)
+        }
 
-            utils::reverse_string(result.as_str())
-        } else {
-            String::from(convert_translator.chars().nth(0).unwrap())
+        let mut value: u128 = 0;
+        for digit in digits.iter().rev() {
+            value = value.checked_mul(self.base())
+                .and_then(|value| value.checked_add(*digit as u128 + 1))
+                .ok_or(ShortIdError::OutOfRange)?;
         }
+        Ok(value)
+    }
+
+    /// Luhn mod N sum over the digits, doubling every second digit counted from the right. `doubled_first`
+    /// says whether the rightmost digit is doubled, which is the case when the check digit is not included.
+    fn luhn_sum(&self, digits: &[usize], doubled_first: bool) -> usize {
+        let base = self.alphabets.len();
+        let mut double = doubled_first;
+        let mut sum = 0;
+
+        for digit in digits.iter().rev() {
+            let addend = if double { digit * 2 } else { *digit };
+            sum += addend / base + addend % base;
+            double = !double;
+        }
+        sum % base
+    }
+
+    fn check_digit(&self, digits: &[usize]) -> usize {
+        let base = self.alphabets.len();
+        (base - self.luhn_sum(digits, true)) % base
     }
 
-    pub fn new(alphabets: &String, numbers: &String) -> Self {
-        Self { alphabets: alphabets.to_owned(), numbers: numbers.to_owned() }
+    /// Whether the digits, check digit last, pass the check.
+    fn passes_check(&self, digits: &[usize]) -> bool {
+        self.luhn_sum(digits, false) == 0
+    }
+
+    fn is_decodable(&self, digits: &[usize]) -> bool {
+        self.bijective_value(&digits[..digits.len() - 1]).is_ok_and(|value| value - 1 <= u64::MAX as u128)
+    }
+
+    /// Valid codes close to a code that failed the check.
+    fn suggestions(&self, digits: &[usize]) -> Vec<String> {
+        let mut candidates: Vec<Vec<usize>> = Vec::new();
+        for index in 0..digits.len() - 1 {
+            if digits[index] != digits[index + 1] {
+                let mut swapped = digits.to_vec();
+                swapped.swap(index, index + 1);
+                candidates.push(swapped);
+            }
+        }
+        for index in 0..digits.len() {
+            for digit in (0..self.alphabets.len()).filter(|digit| *digit != digits[index]) {
+                let mut replaced = digits.to_vec();
+                replaced[index] = digit;
+                candidates.push(replaced);
+            }
+        }
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for candidate in candidates {
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break
+            }
+            if !self.passes_check(&candidate) || !self.is_decodable(&candidate) {
+                continue
+            }
+            let code: String = candidate.iter().map(|digit| self.alphabets[*digit]).collect();
+            if !suggestions.contains(&code) {
+                suggestions.push(code);
+            }
+        }
+        suggestions
     }
 
     pub fn to_short(&self, input: u64) -> Result<String, crate::Error> {
-        let input = input.to_string();
-        let converted_to_base = self.convert_base(input, &self.numbers, &self.alphabets, true);
-        Ok(utils::reverse_string(converted_to_base.as_str()))
+        let input = self.obfuscation.as_ref().map_or(input, |feistel| feistel.permute(input));
+        let mut value = input as u128 + 1;
+        let mut digits = Vec::new();
+
+        while value > 0 {
+            digits.push(((value - 1) % self.base()) as usize);
+            value = (value - 1) / self.base();
+        }
+        if self.checksum {
+            digits.push(self.check_digit(&digits));
+        }
+        Ok(digits.iter().map(|digit| self.alphabets[*digit]).collect())
+    }
+
+    fn normalize(&self, input: &str) -> String {
+        input.trim()
+            .chars()
+            .map(|c| if self.alphabets.contains(&c) { c } else { self.normalization.get(&c).copied().unwrap_or(c) })
+            .collect()
+    }
+
+    pub fn decode(&self, input: &str, mode: DecodeMode) -> Result<u64, ShortIdError> {
+        let mut digits = match mode {
+            DecodeMode::Lenient => self.digits(&self.normalize(input))?,
+            _ => self.digits(input)?
+        };
+        if self.checksum && mode != DecodeMode::Legacy {
+            if digits.len() < 2 {
+                return Err(ShortIdError::TooShort)
+            }
+            if !self.passes_check(&digits) {
+                return Err(ShortIdError::TypoDetected { suggestions: self.suggestions(&digits) })
+            }
+            digits.pop();
+        }
+        let value = self.bijective_value(&digits)?;
+
+        match mode {
+            DecodeMode::Standard | DecodeMode::Lenient => {
+                let id = u64::try_from(value - 1).map_err(|_| ShortIdError::OutOfRange)?;
+                Ok(self.obfuscation.as_ref().map_or(id, |feistel| feistel.unpermute(id)))
+            },
+            DecodeMode::Legacy => {
+                let digits: String = value.to_string()
+                    .bytes()
+                    .map(|digit| self.numbers[(digit - b'0') as usize])
+                    .collect();
+                digits.parse().map_err(|_| ShortIdError::OutOfRange)
+            }
+        }
+    }
+
+    pub fn to_number(&self, input: &str) -> Result<u64, crate::Error> {
+        self.decode(input, DecodeMode::Standard).map_err(BackendError::InvalidShortId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::roblox::FakeRobloxApi;
+    use crate::test_support::backend_builder;
+    use super::*;
+
+    const ALPHABET: &str = "abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+    const NUMBERS: &str = "7394015862";
+
+    fn converter() -> IDConverter {
+        IDConverter::new(ALPHABET, NUMBERS)
+    }
+
+    /// Every combination of checksum and obfuscation.
+    fn configured_converters() -> Vec<IDConverter> {
+        let mut converters = Vec::new();
+        for checksum in [false, true] {
+            for key in [None, Some("test key")] {
+                converters.push(converter().with_checksum(checksum).with_obfuscation_key(key));
+            }
+        }
+        converters
+    }
+
+    fn round_trips(converter: &IDConverter, id: u64) {
+        let code = converter.to_short(id).unwrap();
+        for mode in [DecodeMode::Standard, DecodeMode::Lenient] {
+            assert_eq!(converter.decode(&code, mode), Ok(id), "{} decoded from {:?} in {:?} mode", id, code, mode);
+        }
+    }
+
+    #[test]
+    fn round_trips_at_length_boundaries() {
+        for alphabet in ["ab", "abc", ALPHABET] {
+            let converter = IDConverter::new(alphabet, NUMBERS);
+            let k = alphabet.chars().count() as u64;
+            for id in [0, k - 1, k, k * k - 1, k * k, u64::MAX - 1, u64::MAX] {
+                round_trips(&converter, id);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_random_ids() {
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+        let converters = configured_converters();
+        for _ in 0..10_000 {
+            let id: u64 = rng.gen();
+            for converter in &converters {
+                round_trips(converter, id);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_in_every_configuration() {
+        for converter in configured_converters() {
+            for id in [0, 1, 47, 48, 2303, 2304, 1 << 32, u64::MAX - 1, u64::MAX] {
+                round_trips(&converter, id);
+            }
+        }
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let converter = converter();
+        let codes: HashSet<String> = (0..10_000).map(|id| converter.to_short(id).unwrap()).collect();
+        assert_eq!(codes.len(), 10_000);
+    }
+
+    #[test]
+    fn reports_invalid_characters_with_their_index() {
+        assert_eq!(converter().decode("", DecodeMode::Standard), Err(ShortIdError::Empty));
+        assert_eq!(
+            converter().decode("ab0c", DecodeMode::Standard),
+            Err(ShortIdError::InvalidCharacter { character: '0', index: 2 })
+        );
+        assert_eq!(
+            converter().decode("abcé", DecodeMode::Standard),
+            Err(ShortIdError::InvalidCharacter { character: 'é', index: 3 })
+        );
+    }
+
+    #[test]
+    fn rejects_codes_past_u64_max() {
+        let converter = converter();
+        let longest = converter.to_short(u64::MAX).unwrap();
+        let mut past_max = longest.clone();
+        past_max.push('a');
+        assert_eq!(converter.decode(&past_max, DecodeMode::Standard), Err(ShortIdError::OutOfRange));
+
+        let over_long = "Z".repeat(40);
+        assert_eq!(converter.decode(&over_long, DecodeMode::Standard), Err(ShortIdError::OutOfRange));
     }
 
-    pub fn to_number(&self, input: String) -> Result<u64, crate::Error> {
-        let converted_to_base = self.convert_base(utils::reverse_string(input.as_str()), &self.alphabets, &self.numbers, false);
-        let id_from_converted = converted_to_base.parse::<u64>();
-        match id_from_converted {
-            Ok(id) => Ok(id),
-            Err(_) => Err("Transformed ID is not a number. Input possibly error/corrupted.".into())
+    #[test]
+    fn legacy_mode_decodes_codes_of_the_old_converter() {
+        // Produced by `to_short` before the codec was rewritten, with the same alphabets.
+        let fixture = [
+            (0, "d"),
+            (1, "e"),
+            (5, "f"),
+            (42, "Q"),
+            (1234, "iCb"),
+            (98765, "pMk"),
+            (1000000, "Vakaa"),
+            (2718281828459045, "veFfXahwJf")
+        ];
+        let converter = converter().with_checksum(true).with_obfuscation_key(Some("test key"));
+        for (id, code) in fixture {
+            assert_eq!(converter.decode(code, DecodeMode::Legacy), Ok(id), "legacy code {:?}", code);
         }
     }
-}
\ No newline at end of file
+
+    fn swapped(code: &str, index: usize) -> String {
+        let mut characters: Vec<char> = code.chars().collect();
+        characters.swap(index, index + 1);
+        characters.into_iter().collect()
+    }
+
+    fn replaced(code: &str, index: usize, character: char) -> String {
+        code.chars().enumerate().map(|(position, c)| if position == index { character } else { c }).collect()
+    }
+
+    /// Asserts the typo is caught, and that `original` is suggested if `suggested` is set.
+    fn assert_typo_detected(converter: &IDConverter, typo: &str, original: &str, suggested: bool) {
+        match converter.decode(typo, DecodeMode::Standard) {
+            Err(ShortIdError::TypoDetected { suggestions }) => assert!(
+                !suggested || suggestions.iter().any(|suggestion| suggestion == original),
+                "{:?} not suggested for {:?}: {:?}", original, typo, suggestions
+            ),
+            result => panic!("{:?} (typo of {:?}) decoded to {:?}", typo, original, result)
+        }
+    }
+
+    #[test]
+    fn checksum_catches_single_wrong_characters() {
+        let converter = converter().with_checksum(true);
+        for id in [0, 1234, 98765, 3141592653, u64::MAX] {
+            let code = converter.to_short(id).unwrap();
+            // Every position has exactly one replacement passing the check, so the original only makes it
+            // into the suggestions when the code has no more positions than there are suggestions.
+            let suggested = code.chars().count() <= MAX_SUGGESTIONS;
+            for index in 0..code.chars().count() {
+                for character in ALPHABET.chars().filter(|c| code.chars().nth(index) != Some(*c)) {
+                    assert_typo_detected(&converter, &replaced(&code, index, character), &code, suggested);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_catches_every_wrong_character_of_even_alphabets() {
+        for alphabet in ["ab", "abcd", "abcdef", "abcdefgh"] {
+            let converter = IDConverter::new(alphabet, NUMBERS).with_checksum(true);
+            for id in 0..200 {
+                let code = converter.to_short(id).unwrap();
+                for index in 0..code.chars().count() {
+                    for character in alphabet.chars().filter(|c| code.chars().nth(index) != Some(*c)) {
+                        let typo = replaced(&code, index, character);
+                        assert!(converter.decode(&typo, DecodeMode::Standard).is_err(), "{:?} passed as {:?}", typo, code);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_needs_an_even_alphabet() {
+        // Doubling maps b and d to the same value mod 5, so a wrong first character goes unnoticed.
+        let odd = IDConverter::new("abcde", NUMBERS).with_checksum(true);
+        assert_eq!(odd.to_short(1).unwrap(), "bd");
+        assert_eq!(odd.decode("dd", DecodeMode::Standard), Ok(3));
+
+        assert!(IDConverter::validate_checksum_alphabet("abcde").is_err());
+        assert!(IDConverter::validate_checksum_alphabet("abcdef").is_ok());
+        let result = block_on(backend_builder(FakeRobloxApi::new()).id_alphabets("abcde", NUMBERS).id_checksum(true).build());
+        assert!(matches!(result, Err(BackendError::InvalidConfig(_))), "{:?}", result.err());
+        let result = block_on(backend_builder(FakeRobloxApi::new())
+            .id_checksum(true)
+            .id_namespace(crate::IdNamespace::new("maps", "M-", "ABC"))
+            .build());
+        assert!(matches!(result, Err(BackendError::InvalidConfig(_))), "{:?}", result.err());
+        assert!(block_on(backend_builder(FakeRobloxApi::new()).id_alphabets("abcde", NUMBERS).build()).is_ok());
+    }
+
+    #[test]
+    fn checksum_catches_swapped_neighbours() {
+        let converter = converter().with_checksum(true);
+        for id in [1234, 98765, 3141592653, u64::MAX] {
+            let code = converter.to_short(id).unwrap();
+            for index in 0..code.chars().count() - 1 {
+                let typo = swapped(&code, index);
+                if typo != code {
+                    assert_typo_detected(&converter, &typo, &code, true);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_needs_a_character_besides_the_check_character() {
+        let converter = converter().with_checksum(true);
+        let shortest = converter.to_short(0).unwrap();
+        assert_eq!(shortest.chars().count(), 2);
+        assert_eq!(converter.decode(&shortest[..1], DecodeMode::Standard), Err(ShortIdError::TooShort));
+        assert_eq!(converter.decode("", DecodeMode::Standard), Err(ShortIdError::Empty));
+    }
+
+    #[test]
+    fn legacy_mode_ignores_the_check_character() {
+        let converter = converter().with_checksum(true);
+        for (id, code) in [(0, "d"), (1234, "iCb"), (1000000, "Vakaa")] {
+            assert_eq!(converter.decode(code, DecodeMode::Legacy), Ok(id));
+        }
+    }
+
+    #[test]
+    fn feistel_unpermutes_what_it_permutes() {
+        let feistel = Feistel::new("test key");
+        let mut rng = StdRng::seed_from_u64(0xFE15);
+        let edges = [0, 1, u32::MAX as u64, 1 << 32, u64::MAX - 1, u64::MAX];
+        for value in edges.into_iter().chain((0..10_000).map(|_| rng.gen())) {
+            assert_eq!(feistel.unpermute(feistel.permute(value)), value);
+        }
+    }
+
+    #[test]
+    fn obfuscation_keys_change_codes() {
+        let first = converter().with_obfuscation_key(Some("first key"));
+        let second = converter().with_obfuscation_key(Some("second key"));
+        let plain = converter();
+        for id in [0, 1, 1234, u64::MAX] {
+            let code = first.to_short(id).unwrap();
+            assert_ne!(code, second.to_short(id).unwrap());
+            assert_ne!(code, plain.to_short(id).unwrap());
+            assert_ne!(second.decode(&code, DecodeMode::Standard), Ok(id));
+        }
+    }
+
+    #[test]
+    fn obfuscation_spreads_consecutive_ids() {
+        let differing = |first: &str, second: &str| first.chars().zip(second.chars()).filter(|(a, b)| a != b).count();
+
+        // Without a key consecutive IDs only differ in their first character.
+        let plain = converter();
+        assert_eq!(differing(&plain.to_short(1000).unwrap(), &plain.to_short(1001).unwrap()), 1);
+
+        let obfuscated = converter().with_obfuscation_key(Some("test key"));
+        let codes: Vec<String> = (0..1000).map(|id| obfuscated.to_short(id).unwrap()).collect();
+        for pair in codes.windows(2) {
+            assert!(differing(&pair[0], &pair[1]) > 1, "{:?} and {:?} are neighbours", pair[0], pair[1]);
+        }
+    }
+
+    fn lenient(alphabet: &str, code: &str) -> Result<String, ShortIdError> {
+        let converter = IDConverter::new(alphabet, NUMBERS);
+        let id = converter.decode(code, DecodeMode::Lenient)?;
+        Ok(converter.to_short(id).unwrap())
+    }
+
+    #[test]
+    fn lenient_mode_prefers_case_over_confusable_groups() {
+        // `o` is the other case of `O`, `0` shares its group.
+        assert_eq!(lenient("Oab", "o0b"), Ok("OOb".to_string()));
+        // Both `o` and `0` are in the alphabet, so the group is ambiguous and only case applies.
+        assert_eq!(lenient("o0ab", "Oab"), Ok("oab".to_string()));
+        // `I` is the other case of `i`, not the `l` its group points to.
+        assert_eq!(lenient("ilab", "IL1|"), Ok("illl".to_string()));
+        // With both `I` and `l` in the alphabet, `1` could be either and is left alone.
+        assert_eq!(lenient("Ilab", " iL "), Ok("Il".to_string()));
+        assert_eq!(lenient("Ilab", "1"), Err(ShortIdError::InvalidCharacter { character: '1', index: 0 }));
+    }
+
+    #[test]
+    fn warns_about_confusable_and_case_variant_characters() {
+        let warnings = IDConverter::alphabet_warnings("0OaAIlb", &ConfusableTable::default());
+        assert_eq!(warnings, [
+            AlphabetWarning::Confusable { first: '0', second: 'O' },
+            AlphabetWarning::Confusable { first: 'I', second: 'l' },
+            AlphabetWarning::CaseVariants { first: 'a', second: 'A' }
+        ]);
+        assert_eq!(IDConverter::new("0OaAIlb", NUMBERS).warnings(), warnings);
+        assert!(IDConverter::alphabet_warnings("abcdefghijk", &ConfusableTable::default()).is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::BackendError;
+use crate::id_converter::{other_case, ConfusableTable, IDConverter};
+
+/// Name of the namespace built from the main ID alphabet. Its codes have no prefix.
+pub const DEFAULT_ID_NAMESPACE: &str = "default";
+
+/// A kind of ID with its own alphabet, whose codes start with `prefix`, like `M-` for maps.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IdNamespace {
+    pub name: String,
+    pub prefix: String,
+    pub alphabet: String
+}
+
+/// A decoded code and the namespace it was issued in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedId {
+    pub namespace: String,
+    pub id: u64
+}
+
+impl IdNamespace {
+    pub fn new(name: impl Into<String>, prefix: impl Into<String>, alphabet: impl Into<String>) -> Self {
+        Self { name: name.into(), prefix: prefix.into(), alphabet: alphabet.into() }
+    }
+
+    /// Checks that every code can be traced back to exactly one namespace: names and prefixes must be
+    /// unique, no prefix may start with another, and every prefix needs a character that no code of
+    /// `default_alphabet` can contain, so unprefixed codes never look prefixed. Since lenient decoding
+    /// ignores case and reads confusable characters as alphabet characters, that character may not be one
+    /// in either case nor be confusable with one.
+    pub fn validate(namespaces: &[IdNamespace], default_alphabet: &str, confusables: &ConfusableTable) -> Result<(), crate::Error> {
+        let invalid = |message: String| Err(BackendError::InvalidConfig(message));
+        let mut names = HashSet::new();
+
+        for namespace in namespaces {
+            if namespace.name.is_empty() || namespace.name == DEFAULT_ID_NAMESPACE {
+                return invalid(format!("ID namespace name {:?} is reserved.", namespace.name))
+            }
+            if !names.insert(namespace.name.as_str()) {
+                return invalid(format!("ID namespace {} is defined twice.", namespace.name))
+            }
+            if namespace.prefix.chars().all(|c| can_be_read_as(c, default_alphabet, confusables)) {
+                return invalid(format!("Prefix of ID namespace {} needs a character that cannot be read as part of an unprefixed code, in any case.", namespace.name))
+            }
+            IDConverter::validate_alphabet(&namespace.alphabet)
+                .map_err(|message| BackendError::InvalidConfig(format!("ID namespace {}: {}", namespace.name, message)))?;
+
+            let overlapping = namespaces.iter()
+                .find(|other| other.name != namespace.name && namespace.prefix.starts_with(&other.prefix));
+            if let Some(other) = overlapping {
+                return invalid(format!("Prefix of ID namespace {} starts with the prefix of {}.", namespace.name, other.name))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The code with the prefix removed, if it has this namespace's prefix. `ignore_case` compares ASCII
+    /// letters of the prefix case-insensitively.
+    pub(crate) fn strip_prefix<'a>(&self, code: &'a str, ignore_case: bool) -> Option<&'a str> {
+        let head = code.get(..self.prefix.len())?;
+        let matches = if ignore_case { head.eq_ignore_ascii_case(&self.prefix) } else { head == self.prefix };
+        matches.then(|| &code[self.prefix.len()..])
+    }
+}
+
+/// Whether lenient decoding could read `character`, in either case, as a character of `alphabet`.
+fn can_be_read_as(character: char, alphabet: &str, confusables: &ConfusableTable) -> bool {
+    let mut variants = vec![character];
+    variants.extend(other_case(character));
+    variants.iter().any(|variant| {
+        alphabet.contains(*variant)
+            || other_case(*variant).is_some_and(|other| alphabet.contains(other))
+            || confusables.groups.iter().any(|group| group.contains(*variant) && group.chars().any(|c| alphabet.contains(c)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::roblox::FakeRobloxApi;
+    use crate::test_support::{backend_builder, build};
+    use crate::{Backend, DecodeMode};
+    use super::*;
+
+    const ALPHABET: &str = "abcdefghijkmnpqrstuvwxyz";
+
+    fn maps() -> IdNamespace {
+        IdNamespace::new("maps", "M-", "ABCDEFGHJK")
+    }
+
+    fn lobbies() -> IdNamespace {
+        IdNamespace::new("lobbies", "L-", "ABCDEFGHJK")
+    }
+
+    fn assert_invalid(namespaces: &[IdNamespace]) {
+        let result = IdNamespace::validate(namespaces, ALPHABET, &ConfusableTable::default());
+        assert!(matches!(result, Err(BackendError::InvalidConfig(_))), "{:?} passed validation", namespaces);
+    }
+
+    fn backend() -> Backend {
+        build(backend_builder(FakeRobloxApi::new())
+            .id_alphabets(ALPHABET, "0123456789")
+            .id_obfuscation_key("test key")
+            .id_namespace(maps())
+            .id_namespace(lobbies()))
+    }
+
+    #[test]
+    fn accepts_distinct_namespaces() {
+        assert!(IdNamespace::validate(&[maps(), lobbies()], ALPHABET, &ConfusableTable::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_ambiguous_namespaces() {
+        assert_invalid(&[IdNamespace::new(DEFAULT_ID_NAMESPACE, "D-", "ABC")]);
+        assert_invalid(&[IdNamespace::new("", "D-", "ABC")]);
+        assert_invalid(&[maps(), IdNamespace::new("maps", "N-", "ABC")]);
+        assert_invalid(&[IdNamespace::new("maps", "mp", "ABC")]);
+        assert_invalid(&[maps(), IdNamespace::new("modes", "M-X", "ABC")]);
+        assert_invalid(&[maps(), IdNamespace::new("modes", "M-", "ABC")]);
+        assert_invalid(&[IdNamespace::new("maps", "M-", "AA")]);
+    }
+
+    #[test]
+    fn rejects_prefixes_lenient_decoding_reads_as_codes() {
+        // `M` is `m` in the other case, and `2` is confusable with `z`.
+        assert_invalid(&[IdNamespace::new("maps", "M", "ABC")]);
+        assert_invalid(&[IdNamespace::new("maps", "2", "ABC")]);
+        assert_invalid(&[IdNamespace::new("maps", "Z2", "ABC")]);
+        assert!(IdNamespace::validate(&[IdNamespace::new("maps", "M_", "ABC")], ALPHABET, &ConfusableTable::default()).is_ok());
+
+        let result = block_on(backend_builder(FakeRobloxApi::new())
+            .id_alphabets(ALPHABET, "0123456789")
+            .id_namespace(IdNamespace::new("maps", "M", "ABC"))
+            .build());
+        assert!(matches!(result, Err(BackendError::InvalidConfig(_))), "{:?}", result.err());
+    }
+
+    #[test]
+    fn parses_codes_of_every_namespace() {
+        let backend = backend();
+        let map_code = backend.get_shareable_id("maps", "1234".to_string()).unwrap();
+        let lobby_code = backend.get_shareable_id("lobbies", "1234".to_string()).unwrap();
+        let default_code = backend.get_shareable_id(DEFAULT_ID_NAMESPACE, "1234".to_string()).unwrap();
+        assert!(map_code.starts_with("M-"));
+        assert!(lobby_code.starts_with("L-"));
+
+        for (code, namespace) in [(&map_code, "maps"), (&lobby_code, "lobbies"), (&default_code, DEFAULT_ID_NAMESPACE)] {
+            let parsed = backend.parse_shareable_id(code, DecodeMode::Standard).unwrap();
+            assert_eq!(parsed, ParsedId { namespace: namespace.to_string(), id: 1234 });
+        }
+
+        let sloppy = format!(" {} ", map_code.to_lowercase());
+        assert_eq!(backend.parse_shareable_id(&sloppy, DecodeMode::Lenient).unwrap(), ParsedId { namespace: "maps".to_string(), id: 1234 });
+        assert!(backend.parse_shareable_id(&sloppy, DecodeMode::Standard).is_err());
+    }
+
+    #[test]
+    fn namespaces_give_different_codes() {
+        let backend = backend();
+        let map_code = backend.get_shareable_id("maps", "1234".to_string()).unwrap();
+        let lobby_code = backend.get_shareable_id("lobbies", "1234".to_string()).unwrap();
+        // Same alphabet, so only the per-namespace permutation tells the codes apart after the prefix.
+        assert_ne!(map_code["M-".len()..], lobby_code["L-".len()..]);
+
+        let result = backend.get_shareable_id("replays", "1234".to_string());
+        assert!(matches!(result, Err(BackendError::UnknownIdNamespace(_))), "{:?}", result);
+    }
+}
@@ -1,51 +1,137 @@
+use std::sync::Arc;
+
 use mongodb::{Client, options::ClientOptions};
+use database::rate_limit::{RateLimit, RateLimiter};
+use database::restrictions::EscalationPolicy;
+use database::storage::{CollectionNames, MongoStore, Storage};
+use roblox::RobloxApi;
+use roblox::structs::AuthenticatedUser;
 use id_converter::IDConverter;
 
 pub mod roblox;
 pub mod database;
 pub mod luau;
+mod builder;
+mod config;
+mod error;
 mod id_converter;
+mod id_namespaces;
 mod utils;
+#[cfg(test)]
+mod test_support;
+
+pub use builder::BackendBuilder;
+pub use config::BackendConfig;
+pub use error::BackendError;
+pub use id_converter::{AlphabetWarning, ConfusableTable, DecodeMode, ShortIdError};
+pub use id_namespaces::{IdNamespace, ParsedId, DEFAULT_ID_NAMESPACE};
+
 pub struct Backend {
-    pub(crate) roblox_cookie: String,
-    pub(crate) roblox_xcsrf_token: String,
+    pub(crate) http_client: reqwest::Client,
+    pub(crate) roblox: Arc<dyn RobloxApi>,
+    pub(crate) roblox_user: Option<AuthenticatedUser>,
     pub(crate) id_generator: IDConverter,
-    pub(crate) mongo_client: Option<Client>
+    pub(crate) id_namespaces: Vec<(IdNamespace, IDConverter)>,
+    pub(crate) mongo_client: Option<Client>,
+    pub(crate) storage: Option<Arc<dyn Storage>>,
+    pub(crate) appeal_cooldown: chrono::Duration,
+    pub(crate) escalation_policy: EscalationPolicy,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) default_rate_limit: RateLimit
 }
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Error = BackendError;
 
 impl Backend {
-    #[allow(unused_must_use)]
-    pub fn new(roblox_cookie: String, id_generator_alphabets: Vec<String>) -> Self {
-        if id_generator_alphabets.len() < 2 {
-            panic!("ID Generator must have at least 2 alphabets.");
-        }
-        let id_generator = IDConverter::new(&id_generator_alphabets[0], &id_generator_alphabets[1]);
+    pub fn builder() -> BackendBuilder {
+        BackendBuilder::new()
+    }
+
+    pub async fn from_config(config: BackendConfig) -> Result<Self, Error> {
+        BackendBuilder::from_config(config).build().await
+    }
+
+    /// Shorthand for a builder with just a cookie and the first two ID alphabets. Further alphabets are
+    /// ignored, ID namespaces are added with `BackendBuilder::id_namespace`.
+    pub async fn new(roblox_cookie: String, id_generator_alphabets: Vec<String>) -> Result<Self, Error> {
+        let mut alphabets = id_generator_alphabets.into_iter();
+        let (alphabet, numbers) = match (alphabets.next(), alphabets.next()) {
+            (Some(alphabet), Some(numbers)) => (alphabet, numbers),
+            _ => return Err(BackendError::InvalidConfig("ID Generator must have at least 2 alphabets.".to_string()))
+        };
 
-        let mut backend_self = Self { roblox_cookie: roblox_cookie, roblox_xcsrf_token: String::new(), id_generator: id_generator, mongo_client: None };
-        backend_self.refresh_xcsrf_token();
+        Self::builder()
+            .roblox_cookie(roblox_cookie)
+            .id_alphabets(alphabet, numbers)
+            .build()
+            .await
+    }
 
-        backend_self
-    } 
-    
     pub async fn connect_mongodb(&mut self, mongodb_url: String, default_database: Option<String>) -> Result<(), Error> {
+        self.connect_mongodb_with_collections(mongodb_url, default_database, CollectionNames::default()).await
+    }
+
+    pub async fn connect_mongodb_with_collections(&mut self, mongodb_url: String, default_database: Option<String>, collections: CollectionNames) -> Result<(), Error> {
         let mut mongo_options = ClientOptions::parse(mongodb_url).await?;
         mongo_options.default_database = Some(default_database.unwrap_or("test".to_string()));
         let mongo_client = Client::with_options(mongo_options)?;
 
         self.mongo_client = Some(mongo_client);
+        let store = MongoStore::with_collections(self.get_database()?, collections).await?;
+        self.storage = Some(Arc::new(store));
         Ok(())
     }
 
-    pub fn get_shareable_id(&self, id: String) -> Result<String, Error> {
-        let parsed_id = id.parse::<u64>();
-        match parsed_id {
-            Ok(i) => self.id_generator.to_short(i),
-            Err(_) => Err("ID cannot be converted into integer.".into())
+    /// The code of `id` in the namespace named `kind`, prefix included. `DEFAULT_ID_NAMESPACE` gives
+    /// unprefixed codes from the main alphabet.
+    pub fn get_shareable_id(&self, kind: &str, id: String) -> Result<String, Error> {
+        let parsed_id = id.parse::<u64>()
+            .map_err(|_| BackendError::IdConversion("ID cannot be converted into integer.".to_string()))?;
+        if kind == DEFAULT_ID_NAMESPACE {
+            return self.id_generator.to_short(parsed_id)
         }
+
+        let (namespace, converter) = self.id_namespaces.iter()
+            .find(|(namespace, _)| namespace.name == kind)
+            .ok_or_else(|| BackendError::UnknownIdNamespace(kind.to_string()))?;
+        Ok(format!("{}{}", namespace.prefix, converter.to_short(parsed_id)?))
+    }
+
+    /// Decodes a code from any namespace, telling them apart by prefix. Codes without a known prefix are read
+    /// in the default namespace. `DecodeMode::Legacy` only applies there, prefixed codes are never legacy.
+    pub fn parse_shareable_id(&self, code: &str, mode: DecodeMode) -> Result<ParsedId, Error> {
+        let lenient = mode == DecodeMode::Lenient;
+        let code = if lenient { code.trim() } else { code };
+
+        for (namespace, converter) in &self.id_namespaces {
+            if let Some(body) = namespace.strip_prefix(code, lenient) {
+                let body_mode = if mode == DecodeMode::Legacy { DecodeMode::Standard } else { mode };
+                let id = converter.decode(body, body_mode).map_err(BackendError::InvalidShortId)?;
+                return Ok(ParsedId { namespace: namespace.name.clone(), id })
+            }
+        }
+
+        let id = self.decode_short_id(code, mode)?;
+        Ok(ParsedId { namespace: DEFAULT_ID_NAMESPACE.to_string(), id })
     }
 
     pub fn get_number_id(&self, id: String) -> Result<u64, Error> {
-        self.id_generator.to_number(id)
+        self.id_generator.to_number(&id)
+    }
+
+    /// Decodes a code issued before the codec was rewritten. The same code decodes to a different ID with
+    /// `get_number_id`, so callers need to know which kind of code they hold.
+    pub fn get_legacy_number_id(&self, id: &str) -> Result<u64, Error> {
+        self.decode_short_id(id, DecodeMode::Legacy)
+    }
+
+    /// `DecodeMode::Lenient` accepts codes with the wrong case or confusable characters, like ones read out
+    /// loud or copied from a screenshot.
+    pub fn decode_short_id(&self, id: &str, mode: DecodeMode) -> Result<u64, Error> {
+        self.id_generator.decode(id, mode).map_err(BackendError::InvalidShortId)
+    }
+
+    /// Characters of the configured ID alphabet that lenient decoding can't tell apart.
+    pub fn id_alphabet_warnings(&self) -> Vec<AlphabetWarning> {
+        self.id_generator.warnings()
     }
 }
\ No newline at end of file
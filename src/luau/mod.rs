@@ -9,6 +9,7 @@ fn range<N: Node>(node: N) -> (usize, usize) {
     (start.bytes(), end.bytes())
 }
 
+#[allow(clippy::single_match, clippy::collapsible_match, clippy::op_ref)]
 fn internal_find_from_visit<'a>(function_to_find: &str, block: &'a Block, usage_map: &mut HashMap<Range, Vec<&'a Suffix>>) {
     for stmt in block.stmts() {
         match stmt {
@@ -86,11 +87,11 @@ fn internal_find_from_visit<'a>(function_to_find: &str, block: &'a Block, usage_
 }
 
 impl Backend {
-    pub fn luau_ast_from_string(&self, source: &String) -> Result<Ast, crate::Error> {
+    pub fn luau_ast_from_string(&self, source: &str) -> Result<Ast, crate::Error> {
         Ok(full_moon::parse(source)?)
     }
 
-    pub fn luau_find_global_function_usage<'a>(&'a self, ast: &'a Ast, function_to_find: &str) -> HashMap<Range, Vec<&Suffix>> {
+    pub fn luau_find_global_function_usage<'a>(&'a self, ast: &'a Ast, function_to_find: &str) -> HashMap<Range, Vec<&'a Suffix>> {
         let mut usage_map: HashMap<Range, Vec<&Suffix>> = HashMap::new();
         let block = ast.nodes();
         internal_find_from_visit(function_to_find, block, &mut usage_map);
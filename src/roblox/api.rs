@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::structs::{AuthenticatedUser, ItemDetails};
+
+pub const AUTH_URL: &str = "https://auth.roblox.com";
+pub const ASSETDELIVERY_URL: &str = "https://assetdelivery.roblox.com/v1";
+pub const ECONOMY_V1_URL: &str = "https://economy.roblox.com/v1";
+pub const ECONOMY_V2_URL: &str = "https://economy.roblox.com/v2";
+pub const INVENTORY_URL: &str = "https://inventory.roblox.com/v1";
+pub const USERS_URL: &str = "https://users.roblox.com/v1";
+
+/// Base URLs of the Roblox web APIs, overridable for proxies or local test servers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RobloxUrls {
+    pub auth: String,
+    pub asset_delivery: String,
+    pub economy_v1: String,
+    pub economy_v2: String,
+    pub inventory: String,
+    pub users: String
+}
+
+impl Default for RobloxUrls {
+    fn default() -> Self {
+        Self {
+            auth: AUTH_URL.to_string(),
+            asset_delivery: ASSETDELIVERY_URL.to_string(),
+            economy_v1: ECONOMY_V1_URL.to_string(),
+            economy_v2: ECONOMY_V2_URL.to_string(),
+            inventory: INVENTORY_URL.to_string(),
+            users: USERS_URL.to_string()
+        }
+    }
+}
+
+/// Every Roblox web API call `Backend` makes.
+#[async_trait]
+pub trait RobloxApi: Send + Sync {
+    async fn refresh_xcsrf_token(&self) -> Result<(), crate::Error>;
+    /// The account the configured cookie logs in as. Fails if the cookie is invalid or expired.
+    async fn fetch_authenticated_user(&self) -> Result<AuthenticatedUser, crate::Error>;
+    async fn fetch_asset_details(&self, asset_id: u64) -> Result<ItemDetails, crate::Error>;
+    async fn user_owns_asset(&self, user_id: u64, asset_id: u64) -> Result<bool, crate::Error>;
+    async fn purchase_asset(&self, asset_id: u64) -> Result<(), crate::Error>;
+    async fn download_asset(&self, asset_id: u64) -> Result<Vec<u8>, crate::Error>;
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::BackendError;
+use super::api::RobloxApi;
+use super::structs::{AuthenticatedUser, ItemDetails, RobloxApiError, RobloxError};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetOwnership {
+    #[serde(rename = "userId")]
+    pub user_id: u64,
+    #[serde(rename = "assetId")]
+    pub asset_id: u64
+}
+
+/// Canned Roblox data for `FakeRobloxApi`. Asset entries use the same shape as the economy API response,
+/// so real responses can be saved and reused as fixtures.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RobloxFixtures {
+    #[serde(default)]
+    pub assets: Vec<ItemDetails>,
+    #[serde(default)]
+    pub ownership: Vec<AssetOwnership>
+}
+
+struct FakeState {
+    authenticated_user: Option<AuthenticatedUser>,
+    assets: HashMap<u64, ItemDetails>,
+    ownership: HashSet<(u64, u64)>,
+    asset_bytes: HashMap<u64, Vec<u8>>,
+    purchases: Vec<u64>,
+    xcsrf_refreshes: usize
+}
+
+impl Default for FakeState {
+    fn default() -> Self {
+        Self {
+            authenticated_user: Some(AuthenticatedUser { id: 1, name: "FakeUser".to_string(), display_name: "FakeUser".to_string() }),
+            assets: HashMap::new(),
+            ownership: HashSet::new(),
+            asset_bytes: HashMap::new(),
+            purchases: Vec::new(),
+            xcsrf_refreshes: 0
+        }
+    }
+}
+
+/// Offline stand-in for the Roblox web APIs, answering from fixtures instead of the network. Clones share
+/// their state, so a clone kept after handing the fake to a `Backend` still sees its purchases.
+#[derive(Clone, Default)]
+pub struct FakeRobloxApi {
+    state: Arc<RwLock<FakeState>>
+}
+
+fn api_error(status: u16, message: &str) -> BackendError {
+    BackendError::RobloxApi {
+        status,
+        error: Some(RobloxApiError { errors: vec![RobloxError { code: 0, message: message.to_string() }] })
+    }
+}
+
+fn not_found(message: &str) -> BackendError {
+    api_error(404, message)
+}
+
+impl FakeRobloxApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_fixtures(fixtures: RobloxFixtures) -> Self {
+        let mut fake = Self::new();
+        for asset in fixtures.assets {
+            fake = fake.with_asset(asset);
+        }
+        for ownership in fixtures.ownership {
+            fake = fake.with_owner(ownership.user_id, ownership.asset_id);
+        }
+        fake
+    }
+
+    pub fn from_fixtures_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self::from_fixtures(serde_json::from_str(json)?))
+    }
+
+    /// Pass `None` to act like an invalid or expired cookie.
+    pub fn with_authenticated_user(self, user: Option<AuthenticatedUser>) -> Self {
+        self.state.write().unwrap().authenticated_user = user;
+        self
+    }
+
+    pub fn with_asset(self, details: ItemDetails) -> Self {
+        self.state.write().unwrap().assets.insert(details.id as u64, details);
+        self
+    }
+
+    pub fn with_owner(self, user_id: u64, asset_id: u64) -> Self {
+        self.state.write().unwrap().ownership.insert((user_id, asset_id));
+        self
+    }
+
+    pub fn with_asset_bytes(self, asset_id: u64, bytes: Vec<u8>) -> Self {
+        self.state.write().unwrap().asset_bytes.insert(asset_id, bytes);
+        self
+    }
+
+    /// Asset IDs passed to `purchase_asset`, in call order.
+    pub fn purchased_assets(&self) -> Vec<u64> {
+        self.state.read().unwrap().purchases.clone()
+    }
+
+    pub fn xcsrf_refresh_count(&self) -> usize {
+        self.state.read().unwrap().xcsrf_refreshes
+    }
+}
+
+#[async_trait]
+impl RobloxApi for FakeRobloxApi {
+    async fn refresh_xcsrf_token(&self) -> Result<(), crate::Error> {
+        self.state.write().unwrap().xcsrf_refreshes += 1;
+        Ok(())
+    }
+
+    async fn fetch_authenticated_user(&self) -> Result<AuthenticatedUser, crate::Error> {
+        self.state.read().unwrap()
+            .authenticated_user
+            .clone()
+            .ok_or_else(|| api_error(401, "Authorization has been denied for this request."))
+    }
+
+    async fn fetch_asset_details(&self, asset_id: u64) -> Result<ItemDetails, crate::Error> {
+        self.state.read().unwrap()
+            .assets
+            .get(&asset_id)
+            .cloned()
+            .ok_or_else(|| not_found("Asset not found."))
+    }
+
+    async fn user_owns_asset(&self, user_id: u64, asset_id: u64) -> Result<bool, crate::Error> {
+        Ok(self.state.read().unwrap().ownership.contains(&(user_id, asset_id)))
+    }
+
+    async fn purchase_asset(&self, asset_id: u64) -> Result<(), crate::Error> {
+        let mut state = self.state.write().unwrap();
+        if !state.assets.contains_key(&asset_id) {
+            return Err(not_found("Product not found."))
+        }
+        state.purchases.push(asset_id);
+        Ok(())
+    }
+
+    async fn download_asset(&self, asset_id: u64) -> Result<Vec<u8>, crate::Error> {
+        self.state.read().unwrap()
+            .asset_bytes
+            .get(&asset_id)
+            .cloned()
+            .ok_or_else(|| not_found("Asset not found."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::roblox::structs::AssetType;
+    use crate::test_support::{backend_builder, build};
+    use crate::BackendError;
+    use super::*;
+
+    const OWNER: u64 = 7;
+    const FREE_MODEL: u64 = 1;
+    const NOT_FOR_SALE: u64 = 2;
+    const DECAL: u64 = 3;
+    const PAID_MODEL: u64 = 4;
+    const UNOWNED_MODEL: u64 = 5;
+
+    fn asset(asset_id: u64, asset_type: u8, price: u64, public_domain: bool) -> String {
+        format!(r#"{{
+            "AssetId": {asset_id}, "TargetId": {asset_id}, "ProductId": {asset_id}, "AssetTypeId": {asset_type},
+            "Name": "Asset {asset_id}", "Description": "",
+            "Creator": {{ "Id": 9, "HasVerifiedBadge": false, "CreatorType": "User", "CreatorTargetId": 9, "Name": "Creator" }},
+            "PriceInRobux": {price}, "IsForSale": true, "IsPublicDomain": {public_domain}
+        }}"#)
+    }
+
+    fn fixtures_json() -> String {
+        let assets = [
+            asset(FREE_MODEL, 10, 0, true),
+            asset(NOT_FOR_SALE, 10, 0, false),
+            asset(DECAL, 13, 0, true),
+            asset(PAID_MODEL, 10, 50, true),
+            asset(UNOWNED_MODEL, 10, 0, true)
+        ];
+        let ownership: Vec<String> = [FREE_MODEL, NOT_FOR_SALE, DECAL, PAID_MODEL].iter()
+            .map(|asset_id| format!(r#"{{ "userId": {}, "assetId": {} }}"#, OWNER, asset_id))
+            .collect();
+        format!(r#"{{ "assets": [{}], "ownership": [{}] }}"#, assets.join(","), ownership.join(","))
+    }
+
+    fn fake() -> FakeRobloxApi {
+        FakeRobloxApi::from_fixtures_json(&fixtures_json()).unwrap()
+    }
+
+    #[test]
+    fn whitelists_free_owned_models() {
+        let fake = fake();
+        let backend = build(backend_builder(fake.clone()));
+
+        block_on(backend.whitelist_asset(FREE_MODEL, OWNER)).unwrap();
+        assert_eq!(fake.purchased_assets(), [FREE_MODEL]);
+        assert_eq!(backend.roblox_user().unwrap().name, "FakeUser");
+    }
+
+    #[test]
+    fn rejects_assets_that_cannot_be_whitelisted() {
+        let fake = fake();
+        let backend = build(backend_builder(fake.clone()));
+
+        let result = block_on(backend.whitelist_asset(NOT_FOR_SALE, OWNER));
+        assert!(matches!(result, Err(BackendError::AssetNotForSale)), "{:?}", result);
+        let result = block_on(backend.whitelist_asset(DECAL, OWNER));
+        assert!(matches!(result, Err(BackendError::InvalidAssetType(Some(AssetType::Decal)))), "{:?}", result);
+        let result = block_on(backend.whitelist_asset(PAID_MODEL, OWNER));
+        assert!(matches!(result, Err(BackendError::AssetCostsRobux(50))), "{:?}", result);
+        let result = block_on(backend.whitelist_asset(UNOWNED_MODEL, OWNER));
+        assert!(matches!(result, Err(BackendError::AssetNotOwned)), "{:?}", result);
+
+        assert!(fake.purchased_assets().is_empty());
+    }
+
+    #[test]
+    fn downloads_asset_bytes() {
+        let fake = fake().with_asset_bytes(FREE_MODEL, b"<roblox!".to_vec());
+        let backend = build(backend_builder(fake.clone()));
+
+        assert_eq!(block_on(backend.download_asset_bytes(FREE_MODEL)).unwrap(), b"<roblox!");
+        let result = block_on(backend.download_asset_bytes(DECAL));
+        assert!(matches!(result, Err(BackendError::RobloxApi { status: 404, .. })), "{:?}", result);
+    }
+}
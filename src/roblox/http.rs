@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use reqwest::{redirect, Client, Proxy};
+
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Settings for the HTTP client shared by every Roblox request.
+#[derive(Clone, Debug)]
+pub struct HttpClientConfig {
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub user_agent: String,
+    /// Proxy URL applied to every request, e.g. `http://127.0.0.1:8080`.
+    pub proxy: Option<String>
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(30)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn build_client(&self) -> Result<Client, crate::Error> {
+        // Redirects are followed by hand, asset delivery needs to see the CDN location.
+        let mut builder = Client::builder()
+            .user_agent(self.user_agent.as_str())
+            .redirect(redirect::Policy::none());
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
@@ -5,6 +5,7 @@ use rbx_dom_weak::{WeakDom, Instance};
 use rbx_types::Variant;
 use crate::Backend;
 
+#[allow(clippy::ptr_arg)]
 fn search_for_classnames<'a>(dom: &'a WeakDom, classnames: &Vec<&str>, instances: &mut HashMap<Vec<String>, &'a Instance>, mut names: Vec<String>, instance: &'a Instance) {
     names.push(instance.name.clone());
     for &child_ref in instance.children() {
@@ -27,6 +28,7 @@ impl Backend {
         Ok(rbx_binary::from_reader(buf_reader)?)
     }
 
+    #[allow(clippy::needless_borrow, clippy::single_match)]
     pub fn dom_find_scripts<'a>(&'a self, dom: &'a WeakDom) -> HashMap<String, String> {
         let mut scripts: HashMap<String, String> = HashMap::new();
 
@@ -82,6 +82,15 @@ pub struct AssetDeliveryResponse {
     pub asset_type_id: AssetType,
 }
 
+// {"id":1,"name":"Roblox","displayName":"Roblox"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: u64,
+    pub name: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String
+}
+
 // {"errors":[{"code":0,"message":"User is not authorized to access Asset."}]}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RobloxError {
@@ -0,0 +1,411 @@
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
+
+use crate::BackendError;
+use super::api::{RobloxApi, RobloxUrls};
+use super::structs::{AssetPurchaseReq, AuthenticatedUser, ItemDetails, RobloxApiError};
+
+const XCSRF_HEADER: &str = "x-csrf-token";
+
+/// Talks to the real Roblox web APIs, authenticated with a `.ROBLOSECURITY` cookie.
+pub struct RobloxWebClient {
+    http_client: Client,
+    urls: RobloxUrls,
+    cookie: String,
+    xcsrf_token: RwLock<String>,
+    /// Held while a new token is fetched, so concurrent rejections only refresh once.
+    xcsrf_refresh_lock: Mutex<()>
+}
+
+impl RobloxWebClient {
+    pub fn new(http_client: Client, cookie: String, urls: RobloxUrls) -> Self {
+        Self {
+            http_client,
+            urls,
+            cookie,
+            xcsrf_token: RwLock::new(String::new()),
+            xcsrf_refresh_lock: Mutex::new(())
+        }
+    }
+
+    fn xcsrf_token(&self) -> String {
+        self.xcsrf_token.read().unwrap().clone()
+    }
+
+    fn prepare_headers(&self, xcsrf_token: &str) -> Result<header::HeaderMap, crate::Error> {
+        let mut reqwest_headers = header::HeaderMap::new();
+
+        let xcsrf_header = header::HeaderValue::from_str(xcsrf_token)
+            .map_err(|_| BackendError::UnexpectedResponse("X-CSRF token is not a valid header value.".to_string()))?;
+        reqwest_headers.insert(XCSRF_HEADER, xcsrf_header);
+        let mut cookie_header = header::HeaderValue::from_str(&format!(".ROBLOSECURITY={}", self.cookie))
+            .map_err(|_| BackendError::UnexpectedResponse("Roblox cookie is not a valid header value.".to_string()))?;
+        cookie_header.set_sensitive(true);
+        reqwest_headers.insert(header::COOKIE, cookie_header);
+
+        Ok(reqwest_headers)
+    }
+
+    /// Sends an authenticated request. If Roblox rejects the X-CSRF token, the token is refreshed and the
+    /// request is sent once more.
+    async fn send_authenticated<F>(&self, build_request: F) -> Result<Response, crate::Error>
+    where
+        F: Fn(&Client) -> RequestBuilder + Send + Sync
+    {
+        let xcsrf_token = self.xcsrf_token();
+        let response = build_request(&self.http_client)
+            .headers(self.prepare_headers(&xcsrf_token)?)
+            .send()
+            .await?;
+
+        let new_xcsrf_token = match rejected_xcsrf_token(&response) {
+            Some(token) => token,
+            None => return Ok(response)
+        };
+        self.replace_xcsrf_token(&xcsrf_token, new_xcsrf_token).await?;
+
+        let response = build_request(&self.http_client)
+            .headers(self.prepare_headers(&self.xcsrf_token())?)
+            .send()
+            .await?;
+        Ok(response)
+    }
+
+    /// Swaps in a new token, unless another request already replaced `stale_token` while we waited for the lock.
+    async fn replace_xcsrf_token(&self, stale_token: &str, new_token: Option<String>) -> Result<(), crate::Error> {
+        let _guard = self.xcsrf_refresh_lock.lock().await;
+        if self.xcsrf_token() != stale_token {
+            return Ok(())
+        }
+
+        let new_token = match new_token {
+            Some(token) => token,
+            None => self.fetch_xcsrf_token().await?
+        };
+        *self.xcsrf_token.write().unwrap() = new_token;
+        Ok(())
+    }
+
+    async fn fetch_xcsrf_token(&self) -> Result<String, crate::Error> {
+        let request_result = self.http_client
+            .post(&self.urls.auth)
+            .headers(self.prepare_headers(&self.xcsrf_token())?)
+            .send()
+            .await?;
+
+        request_result
+            .headers()
+            .get(XCSRF_HEADER)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string())
+            .ok_or_else(|| BackendError::UnexpectedResponse("Roblox did not return an X-CSRF token.".to_string()))
+    }
+}
+
+/// Roblox answers "Token Validation Failed" with a 403 carrying the token it expects. Returns `None` when the
+/// response is not such a rejection, `Some(None)` when it is but no usable token was attached.
+fn rejected_xcsrf_token(response: &Response) -> Option<Option<String>> {
+    if response.status() != StatusCode::FORBIDDEN {
+        return None
+    }
+    let header = response.headers().get(XCSRF_HEADER)?;
+    Some(header.to_str().ok().map(|x| x.to_string()))
+}
+
+async fn roblox_error(response: Response) -> BackendError {
+    let status = response.status().as_u16();
+    let error = response.json::<RobloxApiError>().await.ok();
+    BackendError::RobloxApi { status, error }
+}
+
+#[async_trait]
+impl RobloxApi for RobloxWebClient {
+    async fn refresh_xcsrf_token(&self) -> Result<(), crate::Error> {
+        let _guard = self.xcsrf_refresh_lock.lock().await;
+        let xcsrf = self.fetch_xcsrf_token().await?;
+
+        *self.xcsrf_token.write().unwrap() = xcsrf;
+        Ok(())
+    }
+
+    async fn fetch_authenticated_user(&self) -> Result<AuthenticatedUser, crate::Error> {
+        let formatted_url = format!("{}/users/authenticated", self.urls.users);
+
+        let request_result = self.send_authenticated(|client| client.get(&formatted_url)).await?;
+
+        if !request_result.status().is_success() {
+            return Err(roblox_error(request_result).await)
+        }
+
+        Ok(request_result.json::<AuthenticatedUser>().await?)
+    }
+
+    async fn download_asset(&self, asset_id: u64) -> Result<Vec<u8>, crate::Error> {
+        let formatted_url = format!(
+            "{}/asset?id={}",
+            self.urls.asset_delivery,
+            asset_id
+        );
+
+        let cdn_redirect_response = self.send_authenticated(|client| client.get(&formatted_url)).await?;
+
+        if cdn_redirect_response.status() != StatusCode::FOUND {
+            return Err(roblox_error(cdn_redirect_response).await)
+        }
+
+        let location = match cdn_redirect_response.headers().get(header::LOCATION).and_then(|x| x.to_str().ok()) {
+            Some(location) => location.to_string(),
+            None => return Err(BackendError::UnexpectedResponse("Roblox did not return location for asset.".to_string()))
+        };
+
+        // The CDN doesn't need our credentials, so they are not forwarded.
+        let request_result = self.http_client
+            .get(location)
+            .send()
+            .await?;
+
+        if request_result.status() != StatusCode::OK {
+            return Err(roblox_error(request_result).await)
+        }
+
+        Ok(request_result.bytes().await?.to_vec())
+    }
+
+    async fn user_owns_asset(&self, user_id: u64, asset_id: u64) -> Result<bool, crate::Error> {
+        let formatted_url = format!(
+            "{}/users/{}/items/Asset/{}/is-owned",
+            self.urls.inventory,
+            user_id,
+            asset_id
+        );
+
+        let request_result = self.send_authenticated(|client| client.get(&formatted_url)).await?;
+
+        if !request_result.status().is_success() {
+            return Err(roblox_error(request_result).await)
+        }
+
+        match request_result.text().await.unwrap_or_default().parse::<bool>() {
+            Ok(res) => Ok(res),
+            Err(_) => Ok(false)
+        }
+    }
+
+    async fn fetch_asset_details(&self, asset_id: u64) -> Result<ItemDetails, crate::Error> {
+        let formatted_url = format!(
+            "{}/assets/{}/details",
+            self.urls.economy_v2,
+            asset_id
+        );
+
+        let request_result = self.send_authenticated(|client| client.get(&formatted_url)).await?;
+
+        if !request_result.status().is_success() {
+            return Err(roblox_error(request_result).await)
+        }
+
+        Ok(request_result.json::<ItemDetails>().await?)
+    }
+
+    async fn purchase_asset(&self, asset_id: u64) -> Result<(), crate::Error> {
+        let formatted_url = format!(
+            "{}/purchases/products/{}",
+            self.urls.economy_v1,
+            asset_id
+        );
+
+        let request_body = AssetPurchaseReq {
+            expected_currency: 1,
+            expected_price: 0,
+        };
+
+        let request_result = self.send_authenticated(|client| client.post(&formatted_url).json(&request_body)).await?;
+
+        if !request_result.status().is_success() {
+            return Err(roblox_error(request_result).await)
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier, Mutex as StdMutex};
+    use std::thread;
+
+    use futures::future::join_all;
+
+    use super::*;
+
+    const USER_JSON: &str = r#"{"id":1,"name":"Builder","displayName":"Builder"}"#;
+
+    /// A request as the stub saw it.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct StubRequest {
+        method: String,
+        path: String,
+        xcsrf_token: String
+    }
+
+    /// Status, headers and body to answer with.
+    type StubResponse = (u16, Vec<(&'static str, String)>, String);
+
+    /// A local HTTP server standing in for Roblox, answering every request with `respond` on its own thread.
+    struct StubServer {
+        url: String,
+        requests: Arc<StdMutex<Vec<StubRequest>>>
+    }
+
+    impl StubServer {
+        fn start(respond: impl Fn(&StubRequest) -> StubResponse + Send + Sync + 'static) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(StdMutex::new(Vec::new()));
+            let respond = Arc::new(respond);
+
+            let recorded = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let (respond, recorded) = (respond.clone(), recorded.clone());
+                    thread::spawn(move || serve(stream.unwrap(), respond.as_ref(), &recorded));
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn requests(&self) -> Vec<StubRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        fn client(&self) -> RobloxWebClient {
+            let urls = RobloxUrls {
+                auth: format!("{}/auth", self.url),
+                users: self.url.clone(),
+                ..RobloxUrls::default()
+            };
+            RobloxWebClient::new(Client::new(), "cookie".to_string(), urls)
+        }
+    }
+
+    fn serve(mut stream: TcpStream, respond: &dyn Fn(&StubRequest) -> StubResponse, recorded: &StdMutex<Vec<StubRequest>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut xcsrf_token = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                match name.to_ascii_lowercase().as_str() {
+                    XCSRF_HEADER => xcsrf_token = value.trim().to_string(),
+                    "content-length" => content_length = value.trim().parse().unwrap(),
+                    _ => {}
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let request = StubRequest { method, path, xcsrf_token };
+        recorded.lock().unwrap().push(request.clone());
+        let (status, headers, body) = respond(&request);
+
+        let mut response = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+        for (name, value) in headers {
+            response += &format!("{}: {}\r\n", name, value);
+        }
+        response += "\r\n";
+        response += &body;
+        stream.write_all(response.as_bytes()).unwrap();
+    }
+
+    fn token_rejection(token: &str) -> StubResponse {
+        let body = r#"{"errors":[{"code":0,"message":"Token Validation Failed"}]}"#.to_string();
+        (403, vec![(XCSRF_HEADER, token.to_string())], body)
+    }
+
+    fn user() -> StubResponse {
+        (200, vec![("content-type", "application/json".to_string())], USER_JSON.to_string())
+    }
+
+    #[tokio::test]
+    async fn retries_with_the_token_from_a_rejection() {
+        let stub = StubServer::start(|request| {
+            if request.xcsrf_token == "fresh" { user() } else { token_rejection("fresh") }
+        });
+        let client = stub.client();
+
+        assert_eq!(client.fetch_authenticated_user().await.unwrap().id, 1);
+        assert_eq!(client.fetch_authenticated_user().await.unwrap().id, 1);
+
+        let tokens: Vec<String> = stub.requests().into_iter().map(|request| request.xcsrf_token).collect();
+        assert_eq!(tokens, vec!["", "fresh", "fresh"]);
+    }
+
+    #[tokio::test]
+    async fn fetches_a_token_when_the_rejection_has_no_usable_one() {
+        let stub = StubServer::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/auth") => token_rejection("fetched"),
+            _ if request.xcsrf_token == "fetched" => user(),
+            _ => token_rejection("t\u{f6}ken")
+        });
+
+        assert_eq!(stub.client().fetch_authenticated_user().await.unwrap().id, 1);
+        let paths: Vec<String> = stub.requests().into_iter().map(|request| request.path).collect();
+        assert_eq!(paths, vec!["/users/authenticated", "/auth", "/users/authenticated"]);
+    }
+
+    #[tokio::test]
+    async fn other_rejections_are_not_retried() {
+        let stub = StubServer::start(|_| (403, Vec::new(), r#"{"errors":[{"code":0,"message":"Forbidden"}]}"#.to_string()));
+
+        let result = stub.client().fetch_authenticated_user().await;
+        assert!(matches!(result, Err(BackendError::RobloxApi { status: 403, .. })), "{:?}", result);
+        assert_eq!(stub.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_rejections_replace_the_token_once() {
+        const CONCURRENT: usize = 8;
+        // Holds back the first answers until every request was rejected, so all of them race to replace it.
+        let barrier = Barrier::new(CONCURRENT);
+        let issued = AtomicUsize::new(0);
+        let stub = StubServer::start(move |request| {
+            if request.xcsrf_token.starts_with("token-") {
+                return user()
+            }
+            let token = format!("token-{}", issued.fetch_add(1, Ordering::SeqCst));
+            barrier.wait();
+            token_rejection(&token)
+        });
+        let client = stub.client();
+
+        let results = join_all((0..CONCURRENT).map(|_| client.fetch_authenticated_user())).await;
+        assert!(results.iter().all(|result| result.is_ok()), "{:?}", results);
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), CONCURRENT * 2);
+        let retried_with: Vec<&str> = requests.iter()
+            .map(|request| request.xcsrf_token.as_str())
+            .filter(|token| !token.is_empty())
+            .collect();
+        assert_eq!(retried_with.len(), CONCURRENT);
+        assert!(retried_with.iter().all(|token| *token == client.xcsrf_token()), "{:?}", retried_with);
+    }
+}
@@ -0,0 +1,24 @@
+//! Fixtures shared by the tests of several modules.
+
+use futures::executor::block_on;
+
+use crate::database::storage::MemoryStore;
+use crate::roblox::FakeRobloxApi;
+use crate::{Backend, BackendBuilder};
+
+/// A builder for a backend on `roblox` and a fresh `MemoryStore`, for tests to configure further.
+pub fn backend_builder(roblox: FakeRobloxApi) -> BackendBuilder {
+    Backend::builder()
+        .roblox_api(roblox)
+        .storage(MemoryStore::new())
+        .id_alphabets("abcdefghijkmnpqrstuvwxyz", "0123456789")
+}
+
+pub fn build(builder: BackendBuilder) -> Backend {
+    block_on(builder.build()).unwrap()
+}
+
+/// A backend on an empty `FakeRobloxApi` and a fresh `MemoryStore`.
+pub fn backend() -> Backend {
+    build(backend_builder(FakeRobloxApi::new()))
+}